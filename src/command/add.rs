@@ -1,32 +1,111 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
+use serde::Serialize;
 
-use crate::{HostFile, Connection, NetSH, ProxyBridge};
+use crate::{HostFile, Change, Connection, Error, ForwardingBackend, Journal, PortStatus, Report, State, Transaction, is_wildcard, DEFAULT_LISTEN_PORT};
 
 
+#[derive(Debug, Serialize)]
+pub struct Added {
+	pub host: String,
+	pub connect_to: Connection,
+	pub listen_to: Connection,
+	/// Empty if the host already existed.
+	pub changes: Vec<Change>,
+	/// Whether something is listening on the target yet.
+	pub target: PortStatus
+}
 
-pub fn process(address_str: &str, host: &str, netsh: &mut NetSH, hosts: &mut HostFile) -> Result<()> {
-	let connect_to: Connection = address_str.parse()?;
+impl Report for Added {
+	fn table(&self) -> String {
+		let new_bridge = self.changes.iter().any(|v| matches!(v, Change::AddBridge(_)));
+		let new_host = self.changes.iter().any(|v| matches!(v, Change::AddHost { .. }));
 
-	let (bridge, is_new) = netsh.add_or_retreive(connect_to, ProxyBridge::V4ToV4)?;
+		let added = match (new_bridge, new_host) {
+			(false, false) => format!("Host already exists for {}", self.connect_to),
+			(true, false) => format!("Added {} to new Bridge on port {} for {:?}.", self.connect_to, self.listen_to.port, self.host),
+			(true, true) => format!("Added {} to new Bridge for {:?}.", self.connect_to, self.host),
+			(false, true) => format!("Added {} to existing Bridge for {:?}.", self.connect_to, self.host)
+		};
 
-	// If it's not newly added.
-	if !is_new && hosts.find_item_by_host(host).is_some() {
-		println!("Host already exists for {}", connect_to);
-		return Ok(());
+		match self.target {
+			PortStatus::NotListening => format!("{}\nWarning: Nothing is listening on {} yet.", added, self.connect_to),
+			PortStatus::Listening { .. } => added
+		}
 	}
+}
+
+
+/// Bridges the host to the address. The listening port defaults to the existing bridges' or 80.
+pub fn process(
+	address_str: &str,
+	host: &str,
+	listen_port: Option<u16>,
+	backend: &mut dyn ForwardingBackend,
+	hosts: &mut HostFile,
+	state: &mut State,
+	journal: &mut Journal
+) -> Result<Added> {
+	let connect_to: Connection = address_str.parse()?;
+
+	let mut added = Transaction::run(backend, hosts, state, journal, |tx| {
+		let listen_to = add_in(tx, connect_to, host, listen_port)?;
+
+		Ok(Added {
+			host: host.to_string(),
+			connect_to,
+			listen_to,
+			changes: tx.changes(),
+			target: PortStatus::NotListening
+		})
+	})?;
+
+	// Only a warning. The target may be started later.
+	added.target = connect_to.status();
 
+	Ok(added)
+}
 
-	// Add Host, Delete Bridge IF Error.
-	if let Err(e) = hosts.add(bridge.listen_to.address, host.to_string()) {
-		netsh.delete(connect_to, ProxyBridge::V4ToV4)?;
-		return Err(e);
+/// Bridges the host to the address as part of the transaction. Returns the listening connection.
+pub(crate) fn add_in(tx: &mut Transaction, connect_to: Connection, host: &str, listen_port: Option<u16>) -> Result<Connection> {
+	// Includes what the transaction changed so far.
+	let state = tx.current_state();
+	let existing = state.find_by_connection(connect_to).map(|v| v.listen_to);
+
+	let port = match (existing, listen_port) {
+		(Some(listen_to), Some(port)) if listen_to.port != port => {
+			bail!("{} is already listening on port {}", connect_to, listen_to.port);
+		}
+
+		(Some(listen_to), _) => listen_to.port,
+		(None, port) => port.unwrap_or(DEFAULT_LISTEN_PORT)
+	};
+
+	for mapping in state.filter_by_host(host) {
+		// Host is already pointing at another bridge on this port
+		// or the bridge is on a different address than the host.
+		let is_taken = mapping.listen_to.port == port && mapping.connect_to != connect_to;
+		let is_elsewhere = existing.map(|v| v.address != mapping.listen_to.address).unwrap_or_default();
+
+		if is_taken || is_elsewhere {
+			return Err(Error::DuplicateBridge {
+				host: host.to_string(),
+				connect_to: mapping.connect_to
+			}.into());
+		}
 	}
 
-	if is_new {
-		println!("Added {} to new Bridge for {:?}.", connect_to, host);
-	} else {
-		println!("Added {} to existing Bridge for {:?}.", connect_to, host);
+	let (bridge, _) = tx.add_or_retreive(connect_to, host, port)?;
+
+	// Hosts already pointing at the address are served on the new port as well.
+	let has_host = match is_wildcard(host) {
+		true => tx.current_state().filter_by_host(host).any(|v| v.listen_to.address == bridge.listen_to.address),
+		false => tx.hosts().find_item_by_host(host).map(|v| v.address == bridge.listen_to.address).unwrap_or_default()
+	};
+
+	// Bridge is removed again if this fails.
+	if !has_host {
+		tx.add_host(bridge.listen_to.address, host.to_string())?;
 	}
 
-	Ok(())
-}
\ No newline at end of file
+	Ok(bridge.listen_to)
+}
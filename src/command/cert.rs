@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{CertStore, HostFile, Leaf, Report, State, is_wildcard, table, write_atomic};
+
+
+#[derive(Debug, Serialize)]
+pub struct Certified {
+	/// Root certificate to import into trust stores.
+	pub root: PathBuf,
+	pub root_created: bool,
+	pub certs: Vec<Cert>,
+	/// Where the root was exported to.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub exported: Option<PathBuf>
+}
+
+#[derive(Debug, Serialize)]
+pub struct Cert {
+	pub host: String,
+	pub names: Vec<String>,
+	pub cert_path: PathBuf,
+	pub key_path: PathBuf,
+	/// Unix timestamp (seconds) of when it expires.
+	pub not_after: i64,
+	pub status: CertStatus
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CertStatus {
+	/// Still valid for the same names.
+	Valid,
+	Issued,
+	/// Expired soon, the names changed or the root was recreated.
+	Renewed
+}
+
+impl Report for Certified {
+	fn table(&self) -> String {
+		let mut lines = Vec::new();
+
+		if self.root_created {
+			lines.push(format!("Created root certificate {}. Import it into your trust stores.", self.root.display()));
+		} else {
+			lines.push(format!("Root certificate {}", self.root.display()));
+		}
+
+		if let Some(path) = &self.exported {
+			lines.push(format!("Exported root certificate to {}", path.display()));
+		}
+
+		if self.certs.is_empty() {
+			lines.push("No hosts to issue certificates for.".to_string());
+		} else {
+			let rows = self.certs.iter()
+				.map(|v| [
+					v.names.join(", "),
+					match v.status {
+						CertStatus::Valid => "valid",
+						CertStatus::Issued => "issued",
+						CertStatus::Renewed => "renewed"
+					}.to_string(),
+					OffsetDateTime::from_unix_timestamp(v.not_after).map(|v| v.date().to_string()).unwrap_or_default(),
+					v.cert_path.display().to_string()
+				])
+				.collect::<Vec<_>>();
+
+			lines.push(String::new());
+			lines.push(table(["NAMES", "STATUS", "EXPIRES", "CERTIFICATE"], &rows));
+		}
+
+		lines.join("\n")
+	}
+}
+
+
+/// How the root is exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+	Pem,
+	Der
+}
+
+
+/// Issues a certificate for every managed hosts file line which has none, or renews it.
+///
+/// The host is the first name of the line. The other names are added as subject alternative names.
+/// Wildcards are only stored in the state and get a certificate of their own.
+pub fn process(hosts: &HostFile, state: &State, store: &CertStore, export: Option<(&Path, Encoding)>, now: OffsetDateTime) -> Result<Certified> {
+	let (root, root_created) = store.root(now)?;
+
+	let lines = hosts.items.iter()
+		.filter(|v| v.managed)
+		.map(|item| (item.host.clone(), item.names().map(String::from).collect::<Vec<_>>()));
+
+	let wildcards = state.mappings.iter()
+		.flat_map(|v| &v.hosts)
+		.filter(|v| is_wildcard(v))
+		.map(|host| (host.clone(), vec![host.clone()]));
+
+	let mut certs = Vec::<Cert>::new();
+
+	for (host, names) in lines.chain(wildcards) {
+		// Same host on multiple lines.
+		if certs.iter().any(|v| v.host == host) {
+			continue;
+		}
+
+		let (leaf, status) = match store.leaf(&host)? {
+			Some(leaf) if !root_created && !leaf.needs_renewal(now) && has_names(&leaf, &names) => (leaf, CertStatus::Valid),
+			Some(_) => (store.issue(&root, &names, now)?, CertStatus::Renewed),
+			None => (store.issue(&root, &names, now)?, CertStatus::Issued)
+		};
+
+		certs.push(Cert {
+			host: leaf.host,
+			names: leaf.names,
+			cert_path: leaf.cert_path,
+			key_path: leaf.key_path,
+			not_after: leaf.not_after,
+			status
+		});
+	}
+
+	let exported = match export {
+		Some((path, Encoding::Pem)) => {
+			write_atomic(path, &store.root_pem()?)?;
+			Some(path.to_path_buf())
+		}
+
+		Some((path, Encoding::Der)) => {
+			write_atomic(path, store.root_der()?)?;
+			Some(path.to_path_buf())
+		}
+
+		None => None
+	};
+
+	Ok(Certified {
+		root: store.root_path(),
+		root_created,
+		certs,
+		exported
+	})
+}
+
+fn has_names(leaf: &Leaf, names: &[String]) -> bool {
+	leaf.names.len() == names.len() && names.iter().all(|v| leaf.names.contains(v))
+}
@@ -0,0 +1,81 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Connection, DnsServer, Error, Report, State, Zone};
+
+
+#[derive(Debug, Serialize)]
+pub struct Responding {
+	pub listen: Connection,
+	/// Resolver names which aren't managed are forwarded to. Refused if None.
+	pub upstream: Option<Connection>,
+	pub records: Vec<Record>,
+	/// Records are reloaded whenever the state changes.
+	#[serde(skip)]
+	state_path: Option<PathBuf>
+}
+
+#[derive(Debug, Serialize)]
+pub struct Record {
+	pub host: String,
+	pub address: IpAddr
+}
+
+impl Report for Responding {
+	fn table(&self) -> String {
+		let mut lines = self.records.iter()
+			.map(|v| format!("Answering {} with {}", v.host, v.address))
+			.collect::<Vec<_>>();
+
+		lines.push(match self.upstream {
+			Some(upstream) => format!("Forwarding everything else to {}", upstream),
+			None => "Refusing everything else".to_string()
+		});
+
+		lines.push(format!("Listening on {} (UDP and TCP)", self.listen));
+
+		if self.state_path.is_some() {
+			lines.push("Hosts added later are answered without restarting.".to_string());
+		}
+
+		lines.join("\n")
+	}
+}
+
+impl Responding {
+	/// Starts the responder. Runs until stopped.
+	pub fn run(self) -> Result<()> {
+		let zone = Zone {
+			records: self.records.into_iter().map(|v| (v.host, v.address)).collect()
+		};
+
+		let server = DnsServer::bind(zone, self.listen, self.upstream)?;
+
+		match self.state_path {
+			Some(path) => server.watch(path).run(),
+			None => server.run()
+		}
+	}
+}
+
+
+pub fn process(listen: Connection, upstream: Option<Connection>, state: &State) -> Result<Responding> {
+	if !listen.address.is_loopback() {
+		return Err(Error::parse(&listen.to_string(), "Expected a loopback address").into());
+	}
+
+	let records = Zone::from_state(state)
+		.records
+		.into_iter()
+		.map(|(host, address)| Record { host, address })
+		.collect();
+
+	Ok(Responding {
+		listen,
+		upstream,
+		records,
+		state_path: state.path().map(PathBuf::from)
+	})
+}
@@ -0,0 +1,269 @@
+use std::{fmt, net::IpAddr};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Bridge, Change, Connection, DeletionType, ForwardingBackend, HostFile, Journal, Mapping, Report, State, Transaction, is_wildcard};
+
+
+/// Drift between the bridges, hosts file and state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Issue {
+	/// Bridge which no hosts point at.
+	OrphanedBridge(Bridge),
+	/// Managed host pointing at an address without a bridge or mapping.
+	OrphanedHost {
+		address: IpAddr,
+		host: String
+	},
+	/// Stored mapping whose bridge no longer exists.
+	MissingBridge(Mapping),
+	/// Stored host which isn't in the hosts file anymore.
+	MissingHost {
+		listen_to: Connection,
+		host: String
+	},
+	/// Bridge with managed hosts which isn't stored in the state.
+	Untracked(Bridge),
+	/// Host which is on multiple managed lines.
+	DuplicateHost {
+		host: String,
+		addresses: Vec<IpAddr>
+	},
+	/// Multiple bridges connecting to the same address.
+	DuplicateBridge {
+		keep: Bridge,
+		duplicate: Bridge
+	},
+	/// Hosts file points the host at a different address than the state.
+	Conflict {
+		host: String,
+		expected: IpAddr,
+		found: IpAddr
+	}
+}
+
+impl fmt::Display for Issue {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::OrphanedBridge(bridge) => write!(f, "Orphaned Bridge {} -> {} has no hosts", bridge.listen_to, bridge.connect_to),
+			Self::OrphanedHost { address, host } => write!(f, "Orphaned Host {:?} points at {} which has no bridge", host, address),
+			Self::MissingBridge(mapping) => write!(f, "Missing Bridge {} -> {} for {:?}", mapping.listen_to, mapping.connect_to, mapping.hosts),
+			Self::MissingHost { listen_to, host } => write!(f, "Missing Host {:?} for {}", host, listen_to.address),
+			Self::Untracked(bridge) => write!(f, "Untracked Bridge {} -> {}", bridge.listen_to, bridge.connect_to),
+			Self::DuplicateHost { host, addresses } => write!(f, "Duplicate Host {:?} on {:?}", host, addresses),
+			Self::DuplicateBridge { keep, duplicate } => write!(f, "Duplicate Bridge {} for {} (using {})", duplicate.listen_to, duplicate.connect_to, keep.listen_to),
+			Self::Conflict { host, expected, found } => write!(f, "Conflict: {:?} points at {} instead of {}", host, found, expected)
+		}
+	}
+}
+
+
+#[derive(Debug, Serialize)]
+pub struct Diagnosis {
+	pub issues: Vec<Issue>,
+	pub fixed: bool,
+	pub changes: Vec<Change>
+}
+
+impl Report for Diagnosis {
+	fn table(&self) -> String {
+		if self.issues.is_empty() {
+			return "No issues found.".to_string();
+		}
+
+		let mut lines = self.issues.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+		lines.push(String::new());
+
+		if self.fixed {
+			lines.extend(self.changes.iter().map(|v| v.to_string()));
+			lines.push(format!("Fixed {} issue(s).", self.issues.len()));
+		} else {
+			lines.push(format!("Found {} issue(s). Run with --fix to repair them.", self.issues.len()));
+		}
+
+		lines.join("\n")
+	}
+}
+
+
+pub fn process(
+	fix: bool,
+	backend: &mut dyn ForwardingBackend,
+	hosts: &mut HostFile,
+	state: &mut State,
+	journal: &mut Journal
+) -> Result<Diagnosis> {
+	let issues = find_issues(backend, hosts, state);
+
+	if issues.is_empty() || !fix {
+		return Ok(Diagnosis {
+			issues,
+			fixed: false,
+			changes: Vec::new()
+		});
+	}
+
+	// Untracked bridges only need to be stored.
+	state.reconcile(backend, hosts);
+	state.save()?;
+
+	let changes = Transaction::run(backend, hosts, state, journal, |tx| {
+		for issue in &issues {
+			match issue {
+				Issue::OrphanedBridge(bridge) => {
+					tx.delete_bridge(bridge.listen_to)?;
+				}
+
+				Issue::OrphanedHost { host, .. } => {
+					tx.delete_hosts(DeletionType::Host(host))?;
+				}
+
+				Issue::MissingBridge(mapping) => {
+					// Reuses the stored listening address.
+					tx.add_or_retreive(mapping.connect_to, mapping.hosts.first().map(String::as_str).unwrap_or_default(), mapping.listen_to.port)?;
+				}
+
+				Issue::MissingHost { listen_to, host } => {
+					tx.add_host(listen_to.address, host.clone())?;
+				}
+
+				Issue::Untracked(_) => (),
+
+				Issue::DuplicateHost { host, addresses } => {
+					let address = tx.state().find_by_host(host)
+						.map(|v| v.listen_to.address)
+						.unwrap_or(addresses[0]);
+
+					tx.delete_hosts(DeletionType::Host(host))?;
+					tx.add_host(address, host.clone())?;
+				}
+
+				Issue::DuplicateBridge { keep, duplicate } => {
+					let moving = tx.hosts().find_by_address(duplicate.listen_to.address)
+						.into_iter()
+						.filter(|v| v.managed)
+						.flat_map(|v| v.names())
+						.map(|v| v.to_string())
+						.collect::<Vec<_>>();
+
+					for host in moving {
+						tx.delete_hosts(DeletionType::Host(&host))?;
+						tx.add_host(keep.listen_to.address, host)?;
+					}
+
+					tx.delete_bridge(duplicate.listen_to)?;
+				}
+
+				Issue::Conflict { host, expected, .. } => {
+					tx.delete_hosts(DeletionType::Host(host))?;
+					tx.add_host(*expected, host.clone())?;
+				}
+			}
+		}
+
+		Ok(tx.changes())
+	})?;
+
+	Ok(Diagnosis {
+		issues,
+		fixed: true,
+		changes
+	})
+}
+
+
+pub fn find_issues(backend: &dyn ForwardingBackend, hosts: &HostFile, state: &State) -> Vec<Issue> {
+	let mut issues = Vec::new();
+
+	let managed = hosts.items.iter().filter(|v| v.managed).collect::<Vec<_>>();
+
+	// Bridges
+	for bridge in backend.bridges() {
+		let mapping = state.find_by_connection(bridge.listen_to);
+		let has_hosts = managed.iter().any(|v| v.address == bridge.listen_to.address);
+
+		// Multiple bridges for the same target. Keep the stored one, otherwise the first.
+		let keep = state.find_by_connection(bridge.connect_to)
+			.and_then(|v| backend.bridges().iter().find(|b| b.listen_to == v.listen_to))
+			.or_else(|| backend.bridges().iter().find(|b| b.connect_to == bridge.connect_to))
+			.filter(|v| v.listen_to != bridge.listen_to);
+
+		if let Some(keep) = keep {
+			issues.push(Issue::DuplicateBridge {
+				keep: keep.clone(),
+				duplicate: bridge.clone()
+			});
+		} else if !has_hosts && mapping.map(|v| v.hosts.is_empty()).unwrap_or(true) {
+			issues.push(Issue::OrphanedBridge(bridge.clone()));
+		} else if mapping.is_none() && has_hosts {
+			issues.push(Issue::Untracked(bridge.clone()));
+		}
+	}
+
+	// Mappings
+	for mapping in &state.mappings {
+		if !backend.contains(mapping.listen_to) {
+			issues.push(Issue::MissingBridge(mapping.clone()));
+		}
+
+		// Wildcards are never in the hosts file.
+		for host in mapping.hosts.iter().filter(|v| !is_wildcard(v)) {
+			match managed.iter().find(|v| v.has_name(host)) {
+				None => issues.push(Issue::MissingHost {
+					listen_to: mapping.listen_to,
+					host: host.clone()
+				}),
+
+				Some(item) if item.address != mapping.listen_to.address => issues.push(Issue::Conflict {
+					host: host.clone(),
+					expected: mapping.listen_to.address,
+					found: item.address
+				}),
+
+				_ => ()
+			}
+		}
+	}
+
+	// Hosts
+	let mut seen = Vec::<&str>::new();
+
+	for item in &managed {
+		for host in item.names() {
+			if seen.contains(&host) {
+				continue;
+			}
+
+			seen.push(host);
+
+			let addresses = managed.iter()
+				.filter(|v| v.has_name(host))
+				.map(|v| v.address)
+				.collect::<Vec<_>>();
+
+			if addresses.len() > 1 {
+				issues.push(Issue::DuplicateHost {
+					host: host.to_string(),
+					addresses
+				});
+				continue;
+			}
+
+			let has_bridge = backend.bridges().iter().any(|v| v.listen_to.address == item.address);
+			let has_mapping = state.mappings.iter().any(|v| v.listen_to.address == item.address);
+
+			// Stored hosts are already covered by the mapping checks.
+			if !has_bridge && !has_mapping && state.find_by_host(host).is_none() {
+				issues.push(Issue::OrphanedHost {
+					address: item.address,
+					host: host.to_string()
+				});
+			}
+		}
+	}
+
+	issues
+}
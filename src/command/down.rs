@@ -0,0 +1,93 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Change, Connection, DeletionType, ForwardingBackend, HostFile, Journal, Report, State, Transaction};
+
+
+#[derive(Debug, Serialize)]
+pub struct Downed {
+	pub project: String,
+	pub changes: Vec<Change>
+}
+
+impl Report for Downed {
+	fn table(&self) -> String {
+		if self.changes.is_empty() {
+			return format!("Nothing to remove for {:?}.", self.project);
+		}
+
+		self.changes.iter().map(|v| v.to_string()).collect::<Vec<_>>().join("\n")
+	}
+}
+
+
+/// Removes every host the project added. Hosts added by others stay, even on the same bridge.
+pub fn process(
+	project_id: &str,
+	backend: &mut dyn ForwardingBackend,
+	hosts: &mut HostFile,
+	state: &mut State,
+	journal: &mut Journal
+) -> Result<Downed> {
+	let owned = owned_by(state, project_id);
+
+	let changes = Transaction::run(backend, hosts, state, journal, |tx| {
+		release_in(tx, &owned)?;
+		Ok(tx.changes())
+	})?;
+
+	Ok(Downed {
+		project: project_id.to_string(),
+		changes
+	})
+}
+
+/// Listening connection and host of everything the project added.
+pub(crate) fn owned_by(state: &State, project_id: &str) -> Vec<(Connection, String)> {
+	state.mappings.iter()
+		.flat_map(|mapping| {
+			mapping.hosts.iter()
+				.filter(move |host| mapping.is_owned_by(host, project_id))
+				.map(move |host| (mapping.listen_to, host.clone()))
+		})
+		.collect()
+}
+
+/// Takes the hosts off their bridges as part of the transaction.
+///
+/// Bridges are deleted once none of their hosts are left. A host is only removed from the hosts file
+/// if no other bridge still has it. Hosts kept for another bridge no longer belong to the project.
+pub(crate) fn release_in(tx: &mut Transaction, released: &[(Connection, String)]) -> Result<()> {
+	if released.is_empty() {
+		return Ok(());
+	}
+
+	let is_released = |listen_to: Connection, host: &str| released.iter().any(|(l, h)| *l == listen_to && h == host);
+
+	let mappings = tx.state().mappings.clone();
+
+	for mapping in &mappings {
+		let is_empty = mapping.hosts.iter().all(|host| is_released(mapping.listen_to, host));
+
+		if is_empty && released.iter().any(|(l, _)| *l == mapping.listen_to) {
+			tx.delete_bridge(mapping.listen_to)?;
+		}
+	}
+
+	let mut entries = released.iter().map(|(l, h)| (l.address, h.as_str())).collect::<Vec<_>>();
+	entries.sort();
+	entries.dedup();
+
+	for (address, host) in entries {
+		let still_used = mappings.iter()
+			.any(|v| v.hosts.iter().any(|h| h == host) && !is_released(v.listen_to, host));
+
+		if !still_used {
+			tx.delete_hosts(DeletionType::Entry(address, host))?;
+		}
+	}
+
+	tx.disown(released);
+
+	Ok(())
+}
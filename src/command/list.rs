@@ -1,22 +1,67 @@
+use std::collections::BTreeMap;
+
 use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Connection, ForwardingBackend, PortStatus, Report, State, table};
 
-use crate::{HostFile, NetSH};
 
+#[derive(Debug, Serialize)]
+pub struct Listed {
+	pub connect_to: Connection,
+	pub listen_to: Connection,
+	pub hosts: Vec<String>,
+	pub status: Status,
+	/// Whether something is listening on the target.
+	pub target: PortStatus,
+	/// Project which added each host.
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	pub owners: BTreeMap<String, String>
+}
 
-pub fn process(netsh: &NetSH, hosts: &HostFile) -> Result<()> {
-	for bridge in &netsh.bridges {
-		let found = hosts.find_by_address(bridge.listen_to.address);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+	Active,
+	/// The bridge was removed outside of localhosting.
+	MissingBridge
+}
 
-		if !found.is_empty() {
-			println!(r#"Listening to "{}" for hosts(s): "#, bridge.connect_to);
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct Listing(pub Vec<Listed>);
 
-			for item in found {
-				println!("\t- {}", item.host);
-			}
-		}
+impl Report for Listing {
+	fn table(&self) -> String {
+		let rows = self.0.iter()
+			.map(|v| [
+				v.connect_to.to_string(),
+				v.listen_to.to_string(),
+				match v.status {
+					Status::Active => "active",
+					Status::MissingBridge => "missing bridge"
+				}.to_string(),
+				v.target.to_string(),
+				v.hosts.join(", ")
+			])
+			.collect::<Vec<_>>();
 
-		println!();
+		table(["TARGET", "LISTEN", "STATUS", "LISTENER", "HOSTS"], &rows)
 	}
+}
+
+
+pub fn process(backend: &dyn ForwardingBackend, state: &State) -> Result<Listing> {
+	let listed = state.mappings.iter()
+		.map(|mapping| Listed {
+			connect_to: mapping.connect_to,
+			listen_to: mapping.listen_to,
+			hosts: mapping.hosts.clone(),
+			status: if backend.contains(mapping.listen_to) { Status::Active } else { Status::MissingBridge },
+			target: mapping.connect_to.status(),
+			owners: mapping.owners.clone()
+		})
+		.collect();
 
-	Ok(())
-}
\ No newline at end of file
+	Ok(Listing(listed))
+}
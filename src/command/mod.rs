@@ -1,4 +1,11 @@
 pub mod add;
 pub mod remove;
 pub mod list;
-pub mod test;
\ No newline at end of file
+pub mod test;
+pub mod serve;
+pub mod restore;
+pub mod doctor;
+pub mod up;
+pub mod down;pub mod dns;
+pub mod cert;
+pub mod probe;
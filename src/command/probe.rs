@@ -0,0 +1,189 @@
+use std::{
+	io::{self, Read, Write},
+	net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+	thread,
+	time::{Duration, Instant}
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Connection, Error, Report, State, Target, table};
+
+
+/// How long each hop may take.
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct Probed(pub Vec<Probe>);
+
+/// Path of a host through one mapping.
+#[derive(Debug, Serialize)]
+pub struct Probe {
+	pub host: String,
+	pub listen_to: Connection,
+	pub connect_to: Connection,
+	pub hops: Vec<Hop>
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hop {
+	pub kind: HopKind,
+	pub passed: bool,
+	pub detail: String
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HopKind {
+	/// The host resolves to the listening address.
+	Resolve,
+	/// The listening address forwards to something.
+	Listen,
+	/// The target accepts connections.
+	Target
+}
+
+impl Probed {
+	/// Whether every hop of every probe passed.
+	pub fn passed(&self) -> bool {
+		self.0.iter().flat_map(|v| &v.hops).all(|v| v.passed)
+	}
+}
+
+impl Report for Probed {
+	fn table(&self) -> String {
+		let rows = self.0.iter()
+			.flat_map(|probe| probe.hops.iter().map(move |hop| [
+				format!("{}:{}", probe.host, probe.listen_to.port),
+				match hop.kind {
+					HopKind::Resolve => "resolve",
+					HopKind::Listen => "listen",
+					HopKind::Target => "target"
+				}.to_string(),
+				if hop.passed { "pass" } else { "FAIL" }.to_string(),
+				hop.detail.clone()
+			]))
+			.collect::<Vec<_>>();
+
+		table(["HOST", "HOP", "RESULT", "DETAIL"], &rows)
+	}
+}
+
+
+/// Probes every mapping of the target. Host names are resolved using `resolve`.
+///
+/// Nothing is started. Whatever is listening on the target is probed.
+pub fn process(addr_or_host: &str, state: &State, resolve: &dyn Fn(&str) -> io::Result<Vec<IpAddr>>) -> Result<Probed> {
+	let target = Target::parse(addr_or_host)?;
+	let mappings = state.find_target(&target);
+
+	if mappings.is_empty() {
+		return Err(Error::NotFound(addr_or_host.to_string()).into());
+	}
+
+	let mut probes = Vec::new();
+
+	for mapping in mappings {
+		let hosts = match target {
+			Target::Host(host, _) => vec![host.to_string()],
+			Target::Connection(_) => mapping.hosts.clone()
+		};
+
+		for host in hosts {
+			// Any subdomain of a wildcard will do.
+			let name = match host.strip_prefix("*.") {
+				Some(suffix) => format!("localhosting-probe.{}", suffix),
+				None => host.clone()
+			};
+
+			probes.push(Probe {
+				hops: vec![
+					resolve_hop(&name, mapping.listen_to.address, resolve),
+					connect_hop(HopKind::Listen, &name, mapping.listen_to),
+					connect_hop(HopKind::Target, &name, mapping.connect_to)
+				],
+				host,
+				listen_to: mapping.listen_to,
+				connect_to: mapping.connect_to
+			});
+		}
+	}
+
+	Ok(Probed(probes))
+}
+
+/// Resolves the host using the systems' resolver. This includes the hosts file.
+pub fn system_resolve(host: &str) -> io::Result<Vec<IpAddr>> {
+	Ok((host, 0).to_socket_addrs()?.map(|v| v.ip()).collect())
+}
+
+/// Waits until the connection accepts connections or the timeout passed.
+pub fn wait_for(connection: Connection, timeout: Duration) -> bool {
+	let started = Instant::now();
+
+	while started.elapsed() < timeout {
+		if TcpStream::connect_timeout(&connection.into(), TIMEOUT).is_ok() {
+			return true;
+		}
+
+		thread::sleep(Duration::from_millis(100));
+	}
+
+	false
+}
+
+fn resolve_hop(host: &str, expected: IpAddr, resolve: &dyn Fn(&str) -> io::Result<Vec<IpAddr>>) -> Hop {
+	let (passed, detail) = match resolve(host) {
+		Ok(found) if found.contains(&expected) => (true, format!("{} -> {}", host, expected)),
+		Ok(found) if found.is_empty() => (false, format!("{} has no addresses", host)),
+		Ok(found) => (false, format!(
+			"{} -> {}, expected {}",
+			host,
+			found.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", "),
+			expected
+		)),
+		Err(e) => (false, format!("Unable to resolve {}: {}", host, e))
+	};
+
+	Hop {
+		kind: HopKind::Resolve,
+		passed,
+		detail
+	}
+}
+
+/// Connects and sends a request for the host. Passes if the connection was accepted.
+fn connect_hop(kind: HopKind, host: &str, connection: Connection) -> Hop {
+	let (passed, detail) = match request(host, connection.into()) {
+		Ok(Some(status)) => (true, format!("{} answered {:?}", connection, status)),
+		Ok(None) => (true, format!("{} accepted the connection", connection)),
+		Err(e) => (false, format!("{} refused: {}", connection, e))
+	};
+
+	Hop {
+		kind,
+		passed,
+		detail
+	}
+}
+
+/// Status line of the response. None if it isn't HTTP (TLS for example).
+fn request(host: &str, address: SocketAddr) -> io::Result<Option<String>> {
+	let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+	stream.set_read_timeout(Some(TIMEOUT))?;
+	stream.set_write_timeout(Some(TIMEOUT))?;
+
+	write!(stream, "HEAD / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", host)?;
+
+	let mut buffer = [0; 256];
+	let len = stream.read(&mut buffer).unwrap_or_default();
+
+	Ok(String::from_utf8_lossy(&buffer[..len])
+		.lines()
+		.next()
+		.filter(|v| v.starts_with("HTTP/"))
+		.map(String::from))
+}
@@ -1,39 +1,80 @@
 use anyhow::Result;
+use serde::Serialize;
 
-use crate::{DeletionType, HostFile, Connection, NetSH, ProxyBridge};
+use crate::{DeletionType, HostFile, Change, Error, ForwardingBackend, Journal, Report, State, Target, Transaction};
 
 
-pub fn process(addr_or_host: &str, netsh: &mut NetSH, hosts: &mut HostFile) -> Result<()> {
-	// Manage Command "file.exe remove 127.0.0.1:8080"
-	if addr_or_host.contains(':') {
-		let connect_to = addr_or_host.parse::<Connection>()?;
+#[derive(Debug, Serialize)]
+pub struct Removed {
+	pub target: String,
+	pub changes: Vec<Change>
+}
 
-		// Find Bridge from Connection Address and Port (ex: 127.0.0.1:8080)
-		if let Some(bridge) = netsh.get_by_connection(connect_to) {
-			let removed = hosts.delete(DeletionType::Address(bridge.listen_to.address))?;
+impl Report for Removed {
+	fn table(&self) -> String {
+		self.changes.iter().map(|v| v.to_string()).collect::<Vec<_>>().join("\n")
+	}
+}
 
-			for host in removed {
-				netsh.delete((host.address, 80).into(), ProxyBridge::V4ToV4)?;
-			}
+
+pub fn process(
+	addr_or_host: &str,
+	backend: &mut dyn ForwardingBackend,
+	hosts: &mut HostFile,
+	state: &mut State,
+	journal: &mut Journal
+) -> Result<Removed> {
+	let target = Target::parse(addr_or_host)?;
+
+	Transaction::run(backend, hosts, state, journal, |tx| {
+		let mappings = tx.state().find_target(&target)
+			.into_iter()
+			.cloned()
+			.collect::<Vec<_>>();
+
+		if mappings.is_empty() {
+			return Err(Error::NotFound(addr_or_host.to_string()).into());
 		}
-	}
 
-	// Manage Command "file.exe remove example.com"
-	else {
-		let removed = hosts.delete(DeletionType::Host(addr_or_host))?;
+		match target {
+			// Manage Command "file.exe remove 127.0.0.1:8080"
+			Target::Connection(_) => {
+				let mapping = &mappings[0];
 
-		for host in removed {
-			// Check to see if we have others in the bridge.
-			if let Some(bridge) = netsh.get_by_connection((host.address, 80).into()) {
-				if hosts.count_addresses(bridge.listen_to.address) != 0 {
-					continue;
+				// Hosts stay if other ports are still listening on the address.
+				let is_last = !tx.state().mappings.iter()
+					.any(|v| v.listen_to.address == mapping.listen_to.address && v.listen_to != mapping.listen_to);
+
+				if is_last {
+					for host in &mapping.hosts {
+						tx.delete_hosts(DeletionType::Host(host))?;
+					}
 				}
+
+				tx.delete_bridge(mapping.listen_to)?;
 			}
 
-			// Delete Bridge if this was the only item in the bridge.
-			netsh.delete((host.address, 80).into(), ProxyBridge::V4ToV4)?;
+			// Manage Command "file.exe remove example.com:443"
+			Target::Host(host, Some(_)) if tx.state().filter_by_host(host).count() > 1 => {
+				tx.delete_bridge(mappings[0].listen_to)?;
+			}
+
+			// Manage Command "file.exe remove example.com"
+			Target::Host(host, _) => {
+				let mappings = tx.state().filter_by_host(host).cloned().collect::<Vec<_>>();
+
+				tx.delete_hosts(DeletionType::Host(host))?;
+
+				// Delete Bridges if this was the only host in the mapping.
+				for mapping in mappings.iter().filter(|v| v.hosts.len() == 1) {
+					tx.delete_bridge(mapping.listen_to)?;
+				}
+			}
 		}
-	}
 
-	Ok(())
-}
\ No newline at end of file
+		Ok(Removed {
+			target: addr_or_host.to_string(),
+			changes: tx.changes()
+		})
+	})
+}
@@ -0,0 +1,69 @@
+use std::fs;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Backups, HostFile, Report};
+
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "action")]
+pub enum Restored {
+	/// Backups, newest first.
+	List {
+		dir: String,
+		backups: Vec<String>
+	},
+	Restore {
+		path: String
+	}
+}
+
+impl Report for Restored {
+	fn table(&self) -> String {
+		match self {
+			Self::List { dir, backups } if backups.is_empty() => format!("No backups found in {}", dir),
+
+			Self::List { backups, .. } => {
+				let mut lines = vec!["Backups (newest first): ".to_string()];
+
+				for (index, name) in backups.iter().enumerate() {
+					lines.push(format!("\t{}. {}", index, name));
+				}
+
+				lines.join("\n")
+			}
+
+			Self::Restore { path } => format!("Restored {}", path)
+		}
+	}
+}
+
+
+pub fn process(backup: Option<&str>, backups: &Backups, hosts: &mut HostFile) -> Result<Restored> {
+	// Manage Command "file.exe restore"
+	let backup = match backup {
+		Some(v) => v,
+		None => {
+			let list = backups.list()?
+				.iter()
+				.map(|v| v.file_name().unwrap_or_default().to_string_lossy().to_string())
+				.collect();
+
+			return Ok(Restored::List {
+				dir: backups.dir.display().to_string(),
+				backups: list
+			});
+		}
+	};
+
+	// Manage Command "file.exe restore 0"
+	let path = backups.find(backup)?;
+
+	// The current contents are also backed up, so a restore can be undone.
+	hosts.replace(&fs::read_to_string(&path)?)?;
+
+	Ok(Restored::Restore {
+		path: path.display().to_string()
+	})
+}
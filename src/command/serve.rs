@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, client::Client, http::{header, uri::Authority}, rt::System, web};
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{CertResolver, Connection, Error, ForwardingBackend, Report, State};
+
+
+/// Host name and listening port to the connection it's forwarded to.
+pub type Routes = HashMap<(String, u16), Connection>;
+
+/// Only bridges listening on this port terminate HTTPS. The others keep serving HTTP.
+pub const HTTPS_PORT: u16 = 443;
+
+/// Headers which only apply to a single connection (RFC 7230) and are never forwarded.
+const HOP_BY_HOP: [&str; 8] = ["connection", "keep-alive", "proxy-authenticate", "proxy-authorization", "te", "trailer", "transfer-encoding", "upgrade"];
+
+
+#[derive(Debug, Serialize)]
+pub struct Serving {
+	pub routes: Vec<Route>,
+	/// Listening addresses of the bridges.
+	pub listening: Vec<Connection>,
+	/// Whether HTTPS is terminated on bridges listening on port 443.
+	pub tls: bool,
+	#[serde(skip)]
+	resolver: Option<CertResolver>
+}
+
+#[derive(Debug, Serialize)]
+pub struct Route {
+	pub host: String,
+	pub port: u16,
+	pub connect_to: Connection
+}
+
+impl Report for Serving {
+	fn table(&self) -> String {
+		if self.routes.is_empty() {
+			return "No hosts to serve.".to_string();
+		}
+
+		self.routes.iter()
+			.map(|v| format!("Proxying {}://{}:{} to {}", if self.is_tls(v.port) { "https" } else { "http" }, v.host, v.port, v.connect_to))
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+}
+
+impl Serving {
+	/// Terminates HTTPS on port 443 using the certificates of the resolver. Every host routed on it needs one.
+	pub fn with_tls(mut self, resolver: CertResolver) -> Result<Self> {
+		if let Some(route) = self.routes.iter().find(|v| v.port == HTTPS_PORT && resolver.find(&v.host).is_none()) {
+			return Err(Error::NotFound(format!("Certificate for {}", route.host)).into());
+		}
+
+		self.tls = true;
+		self.resolver = Some(resolver);
+
+		Ok(self)
+	}
+
+	/// Whether connections on the port are HTTPS.
+	pub fn is_tls(&self, port: u16) -> bool {
+		self.tls && port == HTTPS_PORT
+	}
+
+	/// Starts the proxy. Runs until stopped.
+	pub fn run(self) -> Result<()> {
+		if self.routes.is_empty() {
+			return Ok(());
+		}
+
+		let routes = self.routes.into_iter()
+			.map(|v| ((v.host, v.port), v.connect_to))
+			.collect();
+
+		System::new("Proxy")
+		.block_on(async {
+			init(routes, self.listening, self.resolver).await
+		})?;
+
+		Ok(())
+	}
+}
+
+
+pub fn process(backend: &dyn ForwardingBackend, state: &State) -> Result<Serving> {
+	let mut routes = create_routes(backend, state)
+		.into_iter()
+		.map(|((host, port), connect_to)| Route { host, port, connect_to })
+		.collect::<Vec<_>>();
+
+	routes.sort_by(|a, b| (&a.host, a.port).cmp(&(&b.host, b.port)));
+
+	// Only listen on addresses which have hosts pointing at them.
+	let mut listening = backend.bridges()
+		.iter()
+		.filter(|bridge| state.find_by_connection(bridge.listen_to).map(|v| !v.hosts.is_empty()).unwrap_or_default())
+		.map(|bridge| bridge.listen_to)
+		.collect::<Vec<_>>();
+
+	listening.dedup();
+
+	Ok(Serving {
+		routes,
+		listening,
+		tls: false,
+		resolver: None
+	})
+}
+
+/// Routes every host of the stored mappings, wildcards included, through the bridge of the mapping.
+///
+/// Hosts are lowercased like incoming requests.
+pub fn create_routes(backend: &dyn ForwardingBackend, state: &State) -> Routes {
+	state.mappings.iter()
+		.filter_map(|mapping| backend.bridges().iter().find(|v| v.listen_to == mapping.listen_to).map(|bridge| (mapping, bridge)))
+		.flat_map(|(mapping, bridge)| {
+			mapping.hosts.iter().map(move |host| ((host.to_ascii_lowercase(), bridge.listen_to.port), bridge.connect_to))
+		})
+		.collect()
+}
+
+/// Connection the host is forwarded to. Exact hosts come first, then the longest matching wildcard like the DNS responder.
+pub fn find_route(routes: &Routes, host: &str, port: u16) -> Option<Connection> {
+	if let Some(connect_to) = routes.get(&(host.to_string(), port)) {
+		return Some(*connect_to);
+	}
+
+	routes.iter()
+		.filter(|((_, p), _)| *p == port)
+		.filter_map(|((name, _), connect_to)| Some((name.strip_prefix("*.")?, connect_to)))
+		.filter(|(suffix, _)| host.ends_with(&format!(".{}", suffix)))
+		.max_by_key(|(suffix, _)| suffix.len())
+		.map(|(_, connect_to)| *connect_to)
+}
+
+async fn init(routes: Routes, listening: Vec<Connection>, resolver: Option<CertResolver>) -> Result<()> {
+	let routes = web::Data::new(routes);
+
+	let mut server = HttpServer::new(move || {
+		App::new()
+			.app_data(routes.clone())
+			.data(Client::new())
+			.default_service(web::route().to(forward))
+	});
+
+	for listen_to in listening {
+		server = match &resolver {
+			Some(resolver) if listen_to.port == HTTPS_PORT => server.bind_rustls(listen_to.to_string(), resolver.clone().server_config())?,
+			_ => server.bind(listen_to.to_string())?
+		};
+	}
+
+	server.run().await?;
+
+	Ok(())
+}
+
+async fn forward(
+	req: HttpRequest,
+	body: web::Bytes,
+	routes: web::Data<Routes>,
+	client: web::Data<Client>
+) -> actix_web::Result<HttpResponse> {
+	// Host names are case insensitive and IPv6 addresses are bracketed, so "[::1]:8080" can't just be split on ':'.
+	let host = req.headers()
+		.get(header::HOST)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| v.parse::<Authority>().ok())
+		.map(|v| v.host().to_ascii_lowercase())
+		.unwrap_or_default();
+
+	let port = req.app_config().local_addr().port();
+
+	let connect_to = match find_route(&routes, &host, port) {
+		Some(v) => v,
+		None => return Ok(HttpResponse::NotFound().body(format!("Unknown Host {:?}", host)))
+	};
+
+	let url = format!(
+		"http://{}{}",
+		connect_to,
+		req.uri().path_and_query().map(|v| v.as_str()).unwrap_or("/")
+	);
+
+	let mut forwarding = client.request_from(url, req.head()).no_decompress();
+
+	for name in &HOP_BY_HOP {
+		forwarding.headers_mut().remove(*name);
+	}
+
+	if let Some(addr) = req.peer_addr() {
+		forwarding = forwarding.header("X-Forwarded-For", addr.ip().to_string());
+	}
+
+	let scheme = req.connection_info().scheme().to_string();
+	forwarding = forwarding.header("X-Forwarded-Proto", scheme.clone());
+
+	let response = forwarding.send_body(body)
+		.await
+		.map_err(actix_web::error::ErrorBadGateway)?;
+
+	println!("{} {}://{}{} -> {} ({})", req.method(), scheme, host, req.path(), connect_to, response.status());
+
+	let mut builder = HttpResponse::build(response.status());
+
+	for (name, value) in response.headers().iter().filter(|(name, _)| !HOP_BY_HOP.contains(&name.as_str())) {
+		builder.header(name.clone(), value.clone());
+	}
+
+	Ok(builder.streaming(response))
+}
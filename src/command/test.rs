@@ -1,82 +1,201 @@
+use std::collections::BTreeMap;
+
 use actix_service::ServiceFactory;
-use actix_web::{App, HttpRequest, HttpResponse, HttpServer, dev::{MessageBody, ServiceRequest, ServiceResponse}, guard, http::header, rt::System, web};
+use actix_web::{
+	App, HttpRequest, HttpResponse, HttpServer,
+	dev::{MessageBody, ServiceRequest, ServiceResponse},
+	guard, http::{StatusCode, header, uri::Authority}, rt::System, web
+};
 use anyhow::Result;
+use serde::Serialize;
+
+use crate::{CertResolver, Connection, Error, Mock, MockRequest, PortStatus, Report, State, Target};
+
+
+
+#[derive(Debug, Serialize)]
+pub struct Testing {
+	pub hosts: Vec<String>,
+	/// Addresses the webserver listens on and the port the hosts use for each.
+	pub listening: Vec<TestListener>,
+	/// How each host is answered.
+	pub mocks: BTreeMap<String, Mock>,
+	/// Whether HTTPS is served instead of HTTP.
+	pub tls: bool,
+	#[serde(skip)]
+	resolver: Option<CertResolver>
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestListener {
+	pub connect_to: Connection,
+	pub port: u16,
+	/// Whether something else is already listening on the address.
+	pub status: PortStatus
+}
+
+impl Report for Testing {
+	fn table(&self) -> String {
+		// Explain
+		let mut lines = vec!["Starting Webserver on Host(s)".to_string()];
+
+		for listener in &self.listening {
+			match &listener.status {
+				PortStatus::NotListening => lines.push(format!("Using IP {} for port {}.", listener.connect_to, listener.port)),
+				status => lines.push(format!("Using IP {} for port {}, which is already {}.", listener.connect_to, listener.port, status))
+			}
+		}
+
+		let scheme = if self.tls { "https" } else { "http" };
+
+		for host in &self.hosts {
+			let mode = match self.mocks.get(host) {
+				Some(Mock::Echo) => " (echoing requests)".to_string(),
+				Some(Mock::Static { dir }) => format!(" (serving {})", dir.display()),
+				Some(Mock::Routes { file, .. }) => format!(" (replying from {})", file.display()),
+				_ => String::new()
+			};
+
+			lines.push(format!("Listening on {}://{}{}", scheme, host, mode));
+		}
 
-use crate::{Bridge, Connection, HostFile, HostItem, NetSH};
+		lines.push("You should now be able to use the Host URL to connect.".to_string());
 
-pub fn process(addr_or_host: &str, netsh: &NetSH, hosts: &HostFile) -> Result<()> {
-	println!("Starting HTTP Server");
+		lines.join("\n")
+	}
+}
 
-	// Manage Command "file.exe test 127.0.0.1:8080"
-	let (host_items, bridge) = if addr_or_host.contains(':') {
-		let connect_to = addr_or_host.parse::<Connection>()?;
+impl Testing {
+	/// Answers the host, or every host if None, using the mock.
+	pub fn with_mock(mut self, host: Option<&str>, mock: Mock) -> Result<Self> {
+		match host {
+			Some(host) if !self.mocks.contains_key(host) => return Err(Error::NotFound(host.to_string()).into()),
+			Some(host) => {
+				self.mocks.insert(host.to_string(), mock);
+			}
+			None => self.mocks.values_mut().for_each(|v| *v = mock.clone())
+		}
 
-		// Find Bridge from Connection Address and Port (ex: 127.0.0.1:8080)
-		if let Some(bridge) = netsh.get_by_connection(connect_to) {
-			let items = hosts.find_by_address(bridge.listen_to.address)
-				.into_iter()
-				.cloned()
-				.collect::<Vec<_>>();
+		Ok(self)
+	}
 
-			(items, bridge.clone())
-		} else {
-			println!("Unable to find address.");
-			return Ok(());
+	/// Serves HTTPS using the certificates of the resolver. Every host needs one.
+	pub fn with_tls(mut self, resolver: CertResolver) -> Result<Self> {
+		if let Some(host) = self.hosts.iter().find(|v| resolver.find(v).is_none()) {
+			return Err(Error::NotFound(format!("Certificate for {}", host)).into());
 		}
+
+		self.tls = true;
+		self.resolver = Some(resolver);
+
+		Ok(self)
 	}
 
-	// Manage Command "file.exe test example.com"
-	else if let Some(host) = hosts.find_item_by_host(addr_or_host) {
-		if let Some(bridge) = netsh.get_by_connection((host.address, 80).into()) {
-			(vec![host.clone()], bridge.clone())
-		} else {
-			println!("Unable to find NetSH Bridge.");
-			return Ok(());
+	/// Fails if something else is listening on one of the addresses.
+	pub fn ensure_available(&self) -> Result<()> {
+		for listener in &self.listening {
+			if let PortStatus::Listening { owner } = listener.connect_to.status() {
+				return Err(Error::PortInUse {
+					connection: listener.connect_to,
+					owner
+				}.into());
+			}
 		}
-	} else {
-		println!("Unable to find host.");
-		return Ok(());
+
+		Ok(())
+	}
+
+	/// Starts the webserver. Runs until stopped.
+	pub fn run(self) -> Result<()> {
+		self.ensure_available()?;
+
+		let connections = self.listening.iter().map(|v| v.connect_to).collect();
+
+		System::new("HTTP")
+		.block_on(async move {
+			init(self.mocks, connections, self.resolver).await
+		})?;
+
+		Ok(())
+	}
+}
+
+
+pub fn process(addr_or_host: &str, state: &State) -> Result<Testing> {
+	let target = Target::parse(addr_or_host)?;
+	let mappings = state.find_target(&target);
+
+	let hosts = match target {
+		// Manage Command "file.exe test 127.0.0.1:8080"
+		Target::Connection(_) => mappings.first().map(|v| v.hosts.clone()).unwrap_or_default(),
+		// Manage Command "file.exe test example.com"
+		Target::Host(host, _) => vec![host.to_string()]
 	};
 
-	if host_items.is_empty() {
-		println!("Unable to find Host(s).");
-		return Ok(());
+	// Every port the host(s) listen on.
+	let mut listening = mappings.iter()
+		.map(|v| TestListener {
+			connect_to: v.connect_to,
+			port: v.listen_to.port,
+			status: PortStatus::NotListening
+		})
+		.collect::<Vec<_>>();
+
+	listening.dedup_by_key(|v| v.connect_to);
+
+	for listener in &mut listening {
+		listener.status = listener.connect_to.status();
+	}
+
+	if listening.is_empty() {
+		return Err(Error::NotFound(addr_or_host.to_string()).into());
 	}
 
-	// Explain
-	println!("Starting Webserver on Host(s)");
-	println!("Using IP {}. Ensure it's not being used.", bridge.connect_to);
-	for item in &host_items {
-		println!("Listening on http://{}", item.host);
+	if hosts.is_empty() {
+		return Err(Error::NotFound(format!("Host(s) for {}", addr_or_host)).into());
 	}
-	println!("You should now be able to use the Host URL to connect.");
 
-	System::new("HTTP")
-	.block_on(async {
-		init(host_items, bridge).await
-	})?;
+	let mocks = hosts.iter()
+		.map(|v| (v.clone(), Mock::default()))
+		.collect();
 
-	Ok(())
+	Ok(Testing {
+		hosts,
+		listening,
+		mocks,
+		tls: false,
+		resolver: None
+	})
 }
 
-async fn init(host_items: Vec<HostItem>, bridge: Bridge) -> Result<()> {
-	HttpServer::new(move || {
+async fn init(mocks: BTreeMap<String, Mock>, connections: Vec<Connection>, resolver: Option<CertResolver>) -> Result<()> {
+	// Exact hosts are matched before wildcards.
+	let mut mocks = mocks.into_iter().collect::<Vec<_>>();
+	mocks.sort_by_key(|(host, _)| host.starts_with("*."));
+
+	let mut server = HttpServer::new(move || {
 		let mut app = App::new();
 
-		for item in &host_items {
-			app = create_host_guard(app, item.host.clone());
+		for (host, mock) in &mocks {
+			app = create_host_guard(app, host.clone(), mock.clone());
 		}
 
 		app
-	})
-	.bind(bridge.connect_to.to_string())?
-	.run()
-	.await?;
+	});
+
+	for connect_to in connections {
+		server = match &resolver {
+			Some(resolver) => server.bind_rustls(connect_to.to_string(), resolver.clone().server_config())?,
+			None => server.bind(connect_to.to_string())?
+		};
+	}
+
+	server.run().await?;
 
 	Ok(())
 }
 
-fn create_host_guard<A, B>(app: App<A, B>, host_url: String) -> App<A, B>
+fn create_host_guard<A, B>(app: App<A, B>, host_url: String, mock: Mock) -> App<A, B>
 where
 	B: MessageBody,
 	A: ServiceFactory<
@@ -92,23 +211,58 @@ where
 		.guard(guard::fn_guard(
 			move |req| {
 				(|| -> Option<bool> {
-					let host = req.headers().get(header::HOST)?;
-					Some(host == &host_url)
+					// Host names are case insensitive. Authority keeps the brackets of IPv6 addresses together.
+					let host = req.headers().get(header::HOST)?.to_str().ok()?.parse::<Authority>().ok()?;
+					Some(matches_host(&host_url, &host.host().to_ascii_lowercase()))
 				})()
 				.unwrap_or_default()
 			}
 		))
+		.app_data(web::Data::new(mock))
+		// Every method and path.
+		.default_service(web::route().to(respond))
+	)
+}
 
-		.route("*", web::get().to(|req: HttpRequest| {
-			let host = req.headers()
-				.get(header::HOST)
-				.unwrap()
-				.to_str()
-				.unwrap();
+/// Whether the lowercased host matches. "*.example.com" matches every subdomain.
+fn matches_host(host_url: &str, host: &str) -> bool {
+	let host_url = host_url.to_ascii_lowercase();
 
-			println!("Loaded {}", host);
+	match host_url.strip_prefix("*.") {
+		Some(suffix) => host.strip_suffix(suffix).map(|v| v.len() > 1 && v.ends_with('.')).unwrap_or_default(),
+		None => host_url == host
+	}
+}
 
-			HttpResponse::Ok().body(format!("Viewing Host {:?}", host))
-		}))
-	)
-}
\ No newline at end of file
+async fn respond(req: HttpRequest, body: web::Bytes, mock: web::Data<Mock>) -> HttpResponse {
+	let mut headers = BTreeMap::<String, String>::new();
+
+	for (name, value) in req.headers() {
+		let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+
+		headers.entry(name.to_string())
+			.and_modify(|v| *v = format!("{}, {}", v, value))
+			.or_insert(value);
+	}
+
+	let request = MockRequest {
+		method: req.method().to_string(),
+		host: headers.get(header::HOST.as_str()).cloned().unwrap_or_default(),
+		path: req.path().to_string(),
+		query: req.query_string().to_string(),
+		headers,
+		body: String::from_utf8_lossy(&body).to_string()
+	};
+
+	let response = mock.respond(&request);
+
+	println!("{} {}{} ({})", request.method, request.host, request.path, response.status);
+
+	let mut builder = HttpResponse::build(StatusCode::from_u16(response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+
+	for (name, value) in &response.headers {
+		builder.header(name.as_str(), value.as_str());
+	}
+
+	builder.body(response.body)
+}
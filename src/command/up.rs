@@ -0,0 +1,84 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Change, Connection, ForwardingBackend, HostFile, Journal, Project, Report, State, Transaction, command::{self, down}, is_wildcard};
+
+
+#[derive(Debug, Serialize)]
+pub struct Upped {
+	pub project: String,
+	pub changes: Vec<Change>,
+	/// Hosts which were already up.
+	pub unchanged: Vec<String>
+}
+
+impl Report for Upped {
+	fn table(&self) -> String {
+		self.changes.iter()
+			.map(|v| v.to_string())
+			.chain(self.unchanged.iter().map(|v| format!("{:?} is already up.", v)))
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+}
+
+
+/// Adds every host of the project in a single transaction. Hosts which are already up are skipped.
+///
+/// Either every host is added and owned by the project or nothing is changed.
+pub fn process(
+	project: &Project,
+	backend: &mut dyn ForwardingBackend,
+	hosts: &mut HostFile,
+	state: &mut State,
+	journal: &mut Journal
+) -> Result<Upped> {
+	// Hosts the project added before which were removed from the file or now use another target.
+	let stale = down::owned_by(state, &project.id)
+		.into_iter()
+		.filter(|(listen_to, host)| !is_wanted(project, state, *listen_to, host))
+		.collect::<Vec<_>>();
+
+	Transaction::run(backend, hosts, state, journal, |tx| {
+		tx.claim_for(&project.id);
+		down::release_in(tx, &stale)?;
+
+		let mut unchanged = Vec::new();
+
+		for entry in &project.entries {
+			let is_up = tx.current_state().filter_by_host(&entry.host).any(|v| entry.is_mapped_by(v))
+				&& (is_wildcard(&entry.host) || tx.hosts().find_item_by_host(&entry.host).is_some());
+
+			if is_up {
+				unchanged.push(entry.host.clone());
+				continue;
+			}
+
+			command::add::add_in(tx, entry.target, &entry.host, entry.listen_port)?;
+		}
+
+		Ok(Upped {
+			project: project.id.clone(),
+			changes: tx.changes(),
+			unchanged
+		})
+	})
+}
+
+/// Whether the project still needs the host on the bridge.
+///
+/// Hosts which share the address with others are on every bridge of it, so they're kept as long as the bridge is.
+fn is_wanted(project: &Project, state: &State, listen_to: Connection, host: &str) -> bool {
+	let mapping = match state.find_by_connection(listen_to) {
+		Some(v) => v,
+		None => return false
+	};
+
+	let is_shared = state.mappings.iter()
+		.any(|v| v.listen_to != listen_to && v.listen_to.address == listen_to.address
+			&& v.hosts.iter().any(|h| h == host) && !v.is_owned_by(host, &project.id));
+
+	project.entries.iter()
+		.filter(|v| v.is_mapped_by(mapping))
+		.any(|v| v.host == host || is_shared)
+}
@@ -0,0 +1,1579 @@
+//! Reverse proxy hosts to local addresses using the hosts file and the OS' port forwarding.
+//!
+//! [`Manager`] is the entry point. The command line front-end is a thin wrapper around it.
+
+// https://stackoverflow.com/questions/8652948/using-port-number-in-windows-host-file
+
+
+pub mod command;
+mod manager;
+mod util;
+
+pub use manager::{BackendKind, Manager};
+pub use util::*;
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{MemoryBackend, HostFile, ForwardingBackend, Journal, State, command, Connection, Bridge, Project, Allocator, Strategy, Subnet, Error, Target, Format};
+	use support::{free_port, scratch_path};
+
+	const ADDRESS_HOST_COMBOS: [(&str, &str); 6] = [
+		("127.0.0.1:8080", "one.test"),
+		("127.0.0.1:8080", "a.one.test"),
+		("127.0.0.1:8080", "b.one.test"),
+
+		("127.0.0.1:8081", "two.test"),
+		("127.0.0.1:8081", "a.two.test"),
+
+		("127.0.0.1:8082", "three.test")
+	];
+
+	/// Helpers shared by tests touching the network or the file system.
+	mod support {
+		use std::{net::TcpListener, path::PathBuf};
+
+		/// Port nothing is listening on right now.
+		pub fn free_port() -> u16 {
+			TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+		}
+
+		/// Unique path in the temporary directory. Nothing is created.
+		pub fn scratch_path(name: &str) -> PathBuf {
+			std::env::temp_dir().join(format!("localhosting-{}-{}", name, rand::random::<u32>()))
+		}
+	}
+
+	#[test]
+	fn add_successes() {
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		for (addr, host) in ADDRESS_HOST_COMBOS {
+			command::add::process(addr, host, None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		}
+
+		// Attempt to add a duplicate (Should not add anything)
+		command::add::process(ADDRESS_HOST_COMBOS.last().unwrap().0, ADDRESS_HOST_COMBOS.last().unwrap().1, None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		// Compare bridge listeners against ones which were attempted to add. (each iter for counts will be [3, 2, 1])
+		for bridge in backend.bridges() {
+			let combo_count = ADDRESS_HOST_COMBOS.iter().filter(|(v, _)| bridge.connect_to == v.parse::<Connection>().unwrap()).count();
+
+			let registered_count = hosts.count_addresses(bridge.listen_to.address);
+
+			assert_eq!(combo_count, registered_count, "Unique Bridge Listeners");
+		}
+
+		assert_eq!(3, backend.bridges().len(), "Bridges Count (Unique addresses)");
+		assert_eq!(6, hosts.items.len(), "Hosts Length (Unique Connections in Hosts File)");
+	}
+
+	#[test]
+	fn remove_successes() {
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		for (addr, host) in ADDRESS_HOST_COMBOS {
+			command::add::process(addr, host, None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		}
+
+		// Start off with initial state. Should be correct.
+		assert_eq!(3, backend.bridges().len(), "[Initial] Bridges Count (Unique addresses)");
+		assert_eq!(6, hosts.items.len(), "[Initial] Hosts Length (Unique Connections in Hosts File)");
+
+		// Remove single host only
+		command::remove::process("a.one.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(3, backend.bridges().len(), "[1 Host Removal] Bridges Count (Unique addresses)");
+		assert_eq!(5, hosts.items.len(), "[1 Host Removal] Hosts Length (Unique Connections in Hosts File)");
+
+		// Remove single host only
+		command::remove::process("two.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(3, backend.bridges().len(), "[2 Hosts Removal] Bridges Count (Unique addresses)");
+		assert_eq!(4, hosts.items.len(), "[2 Hosts Removal] Hosts Length (Unique Connections in Hosts File)");
+
+		// Remove multiple hosts and bridge
+		command::remove::process("127.0.0.1:8080", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(2, backend.bridges().len(), "[2 Hosts + Bridge Removal] Bridges Count (Unique addresses)");
+		assert_eq!(2, hosts.items.len(), "[2 Hosts + Bridge Removal] Hosts Length (Unique Connections in Hosts File)");
+	}
+
+	#[test]
+	fn hosts_store_written() {
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8081", "two.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let contents = hosts.store().read().unwrap();
+		assert!(contents.contains("one.test") && contents.contains("two.test"), "Hosts written to store");
+
+		command::remove::process("one.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let contents = hosts.store().read().unwrap();
+		assert!(!contents.contains("one.test") && contents.contains("two.test"), "Host removed from store");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn nat_rules_fake_nft() {
+		use std::{fs, os::unix::fs::PermissionsExt};
+		use crate::{NatRules, NatTool};
+
+		let dir = scratch_path("nft");
+		fs::create_dir_all(&dir).unwrap();
+
+		// Fake "nft" which prints an existing table and records every applied rule set.
+		let program = dir.join("nft");
+		fs::write(&program, format!(
+			"#!/bin/sh\nif [ \"$1\" = \"list\" ]; then\nprintf 'table ip localhosting {{\\n\\tchain output {{\\n\\t\\tip daddr 127.1.2.3 tcp dport 80 dnat to 127.0.0.1:8080\\n\\t}}\\n}}\\n'\nelse\ncat > {}\nfi\n",
+			dir.join("applied").display()
+		)).unwrap();
+		fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+		let (mut backend, mut hosts) = (NatRules::create(NatTool::Nftables, &program).unwrap(), HostFile::default());
+		let mut state = State::default();
+
+		assert_eq!(1, backend.bridges().len(), "Existing rules parsed");
+		assert!(backend.contains("127.0.0.1:8080".parse().unwrap()));
+
+		command::add::process("127.0.0.1:8081", "two.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let applied = fs::read_to_string(dir.join("applied")).unwrap();
+		assert_eq!(backend.ruleset(), applied, "Generated rule set applied");
+		assert!(applied.contains("dnat to 127.0.0.1:8080"));
+		assert!(applied.contains("dnat to 127.0.0.1:8081"));
+
+		command::remove::process("two.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let applied = fs::read_to_string(dir.join("applied")).unwrap();
+		assert!(!applied.contains("dnat to 127.0.0.1:8081"), "Bridge removed from rule set");
+
+		// Listing without root fails. Reading carries on without bridges, changing them doesn't.
+		let denied = dir.join("nft-denied");
+		fs::write(&denied, "#!/bin/sh\necho 'Error: cache initialization failed: Operation not permitted' >&2\nexit 1\n").unwrap();
+		fs::set_permissions(&denied, fs::Permissions::from_mode(0o755)).unwrap();
+
+		let mut backend = NatRules::create(NatTool::Nftables, &denied).unwrap();
+		assert!(backend.bridges().is_empty());
+
+		let error = command::add::process("127.0.0.1:8082", "three.test", None, &mut backend, &mut HostFile::default(), &mut State::default(), &mut Journal::default()).unwrap_err();
+		assert_eq!(71, error.downcast_ref::<Error>().unwrap().exit_code(), "Listing error kept");
+
+		// Fake "iptables" which prints the existing chain and records every call.
+		let program = dir.join("iptables");
+		fs::write(&program, format!(
+			"#!/bin/sh\nif [ \"$3\" = \"-S\" ]; then\nprintf -- '-N LOCALHOSTING\\n-A LOCALHOSTING -d 127.1.2.3/32 -p tcp -m tcp --dport 80 -j DNAT --to-destination 127.0.0.1:8080\\n'\nelse\necho \"$@\" >> {}\nfi\n",
+			dir.join("called").display()
+		)).unwrap();
+		fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+		let mut backend = NatRules::create(NatTool::Iptables, &program).unwrap();
+		assert_eq!("127.1.2.3:80", backend.bridges()[0].listen_to.to_string(), "Existing chain parsed");
+		assert_eq!("127.0.0.1:8080", backend.bridges()[0].connect_to.to_string());
+
+		command::add::process("127.0.0.1:8081", "two.test", None, &mut backend, &mut HostFile::default(), &mut State::default(), &mut Journal::default()).unwrap();
+
+		let called = fs::read_to_string(dir.join("called")).unwrap();
+		assert!(called.contains("-t nat -F LOCALHOSTING"), "Chain flushed");
+		assert!(called.contains("--to-destination 127.0.0.1:8080") && called.contains("--to-destination 127.0.0.1:8081"), "{}", called);
+
+		fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn userspace_routes() {
+		use crate::UserspaceProxy;
+
+		let path = scratch_path("bridges");
+
+		let (mut backend, mut hosts) = (UserspaceProxy::load(path.clone()).unwrap(), HostFile::default());
+		let mut state = State::default();
+
+		for (addr, host) in ADDRESS_HOST_COMBOS {
+			command::add::process(addr, host, None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		}
+
+		// Bridges are persisted between runs.
+		let backend = UserspaceProxy::load(path.clone()).unwrap();
+		assert_eq!(3, backend.bridges().len(), "Bridges reloaded from file");
+
+		let routes = command::serve::create_routes(&backend, &state);
+		assert_eq!(6, routes.len(), "Every host routed");
+
+		for (addr, host) in ADDRESS_HOST_COMBOS {
+			assert_eq!(addr.parse::<Connection>().unwrap(), routes[&(host.to_string(), 80)], "Host routed to its' connection");
+		}
+
+		std::fs::remove_file(path).ok();
+	}
+
+	#[test]
+	fn hosts_file_on_disk() {
+		let path = scratch_path("hosts");
+		std::fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::read(crate::hosts_file_path(path.to_str())).unwrap());
+		let mut state = State::default();
+
+		assert_eq!(1, hosts.items.len(), "Existing hosts read");
+
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let hosts = HostFile::read(path.clone()).unwrap();
+		assert_eq!(2, hosts.items.len(), "Added host written to disk");
+
+		std::fs::remove_file(path).ok();
+	}
+
+	#[test]
+	fn managed_block_only() {
+		use crate::{MemoryStore, BLOCK_BEGIN, BLOCK_END};
+
+		let user_lines = "127.0.0.1 localhost\n127.0.0.1 mytest.com\n";
+
+		let store = MemoryStore { contents: user_lines.to_string() };
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::load(Box::new(store)).unwrap());
+		let mut state = State::default();
+
+		command::add::process("127.0.0.1:8080", "test.com", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let contents = hosts.store().read().unwrap();
+		assert!(contents.starts_with(user_lines), "User lines untouched");
+		assert!(contents.contains(BLOCK_BEGIN) && contents.contains(BLOCK_END), "Managed block created");
+
+		// Hosts outside of the block are never removed.
+		assert!(command::remove::process("mytest.com", &mut backend, &mut hosts, &mut state, &mut Journal::default()).is_err());
+		command::remove::process("test.com", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(user_lines, hosts.store().read().unwrap(), "Only managed block removed");
+		assert_eq!(2, hosts.items.len(), "Unmanaged hosts kept");
+	}
+
+	#[test]
+	fn document_round_trip() {
+		use crate::{HostsDocument, MemoryStore};
+
+		let original = "# Copyright comment\r\n\r\n  127.0.0.1\tlocalhost  app.local # inline\r\n\t\r\n10.0.0.1 other";
+
+		let document = original.parse::<HostsDocument>().unwrap();
+		assert_eq!(original, document.to_string(), "Unmodified document is identical");
+
+		let store = MemoryStore { contents: original.to_string() };
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::load(Box::new(store)).unwrap());
+		let mut state = State::default();
+
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let contents = hosts.store().read().unwrap();
+		assert!(contents.starts_with(original), "Existing lines untouched");
+		assert!(contents.ends_with("one.test\r\n# END localhosting\r\n"), "Line endings matched");
+
+		command::remove::process("one.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(format!("{}\r\n", original), hosts.store().read().unwrap(), "Only the last line was terminated");
+
+		// Lines which aren't understood are kept as they are.
+		let foreign = "fe80::1%lo0\tlocalhost\nnot-an-address example.com\n127.0.0.1 localhost\n";
+		let store = MemoryStore { contents: foreign.to_string() };
+		let mut hosts = HostFile::load(Box::new(store)).unwrap();
+
+		assert_eq!(foreign, hosts.document().to_string());
+		assert_eq!(1, hosts.items.len(), "Only the valid line is an entry");
+
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		assert!(hosts.store().read().unwrap().starts_with(foreign));
+	}
+
+	#[test]
+	fn host_aliases() {
+		use crate::MemoryStore;
+
+		let store = MemoryStore { contents: "127.0.0.1 localhost app.local api.local\n".to_string() };
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::load(Box::new(store)).unwrap());
+		let mut state = State::default();
+
+		assert!(hosts.find_item_by_host("api.local").is_some(), "Aliases found");
+		assert!(hosts.find_item_by_host("API.Local").is_some(), "Names are case insensitive");
+
+		hosts.group_hosts = true;
+
+		for (addr, host) in ADDRESS_HOST_COMBOS {
+			command::add::process(addr, host, None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		}
+
+		assert_eq!(4, hosts.items.len(), "Hosts grouped onto one line per bridge");
+
+		let bridge = backend.get_by_connection("127.0.0.1:8080".parse().unwrap()).unwrap().clone();
+		assert_eq!(3, hosts.count_addresses(bridge.listen_to.address), "Every name counted");
+
+		// A name differing in case is the same host.
+		command::add::process("127.0.0.1:8080", "B.One.Test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		assert_eq!(3, hosts.count_addresses(bridge.listen_to.address), "No case variant added");
+		assert_eq!(3, state.find_by_connection(bridge.listen_to).unwrap().hosts.len());
+
+		// Removing an alias only removes it from the line.
+		command::remove::process("A.ONE.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let item = hosts.find_item_by_host("b.one.test").unwrap();
+		assert_eq!(vec!["one.test", "b.one.test"], item.names().collect::<Vec<_>>());
+		assert_eq!(3, backend.bridges().len(), "Bridge kept while names remain");
+		assert!(!state.find_by_connection(bridge.listen_to).unwrap().hosts.iter().any(|v| v == "a.one.test"), "Stored name removed");
+
+		command::remove::process("one.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::remove::process("b.one.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(2, backend.bridges().len(), "Bridge removed with its' last name");
+		assert!(hosts.find_item_by_host("api.local").is_some(), "Unmanaged aliases untouched");
+	}
+
+	#[test]
+	fn ipv6_connections() {
+		use crate::{Bridge, MemoryStore, ProxyBridge};
+
+		let store = MemoryStore { contents: "127.0.0.1 localhost\n::1 localhost\n".to_string() };
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::load(Box::new(store)).unwrap());
+		let mut state = State::default();
+
+		assert_eq!(2, hosts.items.len(), "IPv6 hosts read");
+
+		command::add::process("[::1]:8080", "six.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let connect_to = "[::1]:8080".parse::<Connection>().unwrap();
+		assert_eq!("[::1]:8080", connect_to.to_string());
+
+		let bridge = backend.get_by_connection(connect_to).unwrap();
+		assert_eq!(ProxyBridge::V4ToV6, ProxyBridge::between(bridge));
+
+		let bridge = Bridge { listen_to: connect_to, connect_to };
+		assert_eq!(ProxyBridge::V6ToV6, ProxyBridge::between(&bridge));
+	}
+
+	#[test]
+	fn backups_and_restore() {
+		use crate::{Backups, FileStore};
+
+		let dir = scratch_path("backups");
+		std::fs::create_dir_all(&dir).unwrap();
+
+		let path = dir.join("hosts");
+		std::fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+		let mut backups = Backups::new(dir.join("backups"));
+		backups.keep = 3;
+
+		let store = FileStore::new(path.clone()).with_backups(backups.clone());
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::load(Box::new(store)).unwrap());
+		let mut state = State::default();
+
+		for (addr, host) in ADDRESS_HOST_COMBOS {
+			command::add::process(addr, host, None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		}
+
+		assert_eq!(3, backups.list().unwrap().len(), "Oldest backups rotated out");
+		assert!(!dir.join(".hosts.localhosting.tmp").exists(), "Temp file renamed");
+
+		// Symlinked hosts files stay links, the file they point to is replaced.
+		#[cfg(unix)]
+		{
+			let link = dir.join("linked");
+			std::os::unix::fs::symlink(&path, &link).unwrap();
+			crate::write_atomic(&link, "127.0.0.1 linked.test\n").unwrap();
+
+			assert!(std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink(), "Link kept");
+			assert_eq!("127.0.0.1 linked.test\n", std::fs::read_to_string(&path).unwrap(), "Target written");
+			assert!(!dir.join(".linked.localhosting.tmp").exists() && !dir.join(".hosts.localhosting.tmp").exists());
+
+			hosts = HostFile::load(Box::new(FileStore::new(path.clone()).with_backups(backups.clone()))).unwrap();
+		}
+
+		// Newest backup is the file before the last host was added.
+		command::restore::process(Some("0"), &backups, &mut hosts).unwrap();
+
+		assert_eq!(6, hosts.items.len(), "Restored previous contents (localhost + 5 hosts)");
+		assert!(!std::fs::read_to_string(&path).unwrap().contains("three.test"), "Restored on disk");
+
+		std::fs::remove_dir_all(dir).ok();
+	}
+
+	/// Shared switch to make the failing backend/store start erroring.
+	type FailSwitch = std::rc::Rc<std::cell::Cell<bool>>;
+
+	struct FailingBackend(MemoryBackend, FailSwitch);
+
+	impl ForwardingBackend for FailingBackend {
+		fn bridges(&self) -> &[crate::Bridge] {
+			self.0.bridges()
+		}
+
+		fn add_bridge(&mut self, bridge: crate::Bridge) -> anyhow::Result<()> {
+			anyhow::ensure!(!self.1.get(), "Failing Backend");
+			self.0.add_bridge(bridge)
+		}
+
+		fn delete_bridge(&mut self, bridge: &crate::Bridge) -> anyhow::Result<()> {
+			anyhow::ensure!(!self.1.get(), "Failing Backend");
+			self.0.delete_bridge(bridge)
+		}
+	}
+
+	#[derive(Debug)]
+	struct FailingStore(crate::MemoryStore, FailSwitch);
+
+	impl crate::HostsStore for FailingStore {
+		fn read(&self) -> anyhow::Result<String> {
+			self.0.read()
+		}
+
+		fn write(&mut self, contents: &str) -> anyhow::Result<()> {
+			anyhow::ensure!(!self.1.get(), "Failing Store");
+			self.0.write(contents)
+		}
+	}
+
+	#[test]
+	fn add_failures() {
+		let (backend_fails, store_fails) = (FailSwitch::default(), FailSwitch::default());
+
+		let mut backend = FailingBackend(MemoryBackend::default(), backend_fails.clone());
+		let mut hosts = HostFile::load(Box::new(FailingStore(Default::default(), store_fails.clone()))).unwrap();
+		let mut state = State::default();
+
+		// Bridge fails, hosts file untouched.
+		backend_fails.set(true);
+		assert!(command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).is_err());
+		assert_eq!(0, hosts.items.len(), "[Backend Failure] Hosts Length");
+
+		// Hosts file fails, new bridge removed.
+		backend_fails.set(false);
+		store_fails.set(true);
+		assert!(command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).is_err());
+		assert_eq!(0, backend.bridges().len(), "[Store Failure] Bridges Count");
+		assert_eq!(0, hosts.items.len(), "[Store Failure] Hosts Length");
+	}
+
+	#[test]
+	fn remove_failures() {
+		let backend_fails = FailSwitch::default();
+
+		let (mut backend, mut hosts) = (FailingBackend(MemoryBackend::default(), backend_fails.clone()), HostFile::default());
+		let mut state = State::default();
+
+		for (addr, host) in ADDRESS_HOST_COMBOS {
+			command::add::process(addr, host, None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		}
+
+		// Hosts are deleted first, then the bridge fails. Hosts have to be added back.
+		backend_fails.set(true);
+		assert!(command::remove::process("127.0.0.1:8080", &mut backend, &mut hosts, &mut state, &mut Journal::default()).is_err());
+		assert!(command::remove::process("three.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).is_err());
+
+		assert_eq!(3, backend.bridges().len(), "Bridges Count (Unique addresses)");
+		assert_eq!(6, hosts.items.len(), "Hosts Length (Hosts restored)");
+
+		// The bridge fails and takes the hosts file down with it, so the hosts can't be added back.
+		struct TrippingBackend(MemoryBackend, FailSwitch);
+
+		impl ForwardingBackend for TrippingBackend {
+			fn bridges(&self) -> &[crate::Bridge] {
+				self.0.bridges()
+			}
+
+			fn add_bridge(&mut self, bridge: crate::Bridge) -> anyhow::Result<()> {
+				self.0.add_bridge(bridge)
+			}
+
+			fn delete_bridge(&mut self, _: &crate::Bridge) -> anyhow::Result<()> {
+				self.1.set(true);
+				anyhow::bail!("Failing Backend")
+			}
+		}
+
+		let tripped = FailSwitch::default();
+		let mut backend = TrippingBackend(MemoryBackend::default(), tripped.clone());
+		let mut hosts = HostFile::load(Box::new(FailingStore(Default::default(), tripped.clone()))).unwrap();
+		let (mut state, mut journal) = (State::default(), Journal::default());
+
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut journal).unwrap();
+
+		let error = command::remove::process("one.test", &mut backend, &mut hosts, &mut state, &mut journal).unwrap_err();
+
+		assert_eq!("Failing Backend", error.root_cause().to_string(), "Original error kept");
+		assert!(format!("{:#}", error).contains("Failing Store"), "Recovery error attached");
+		assert!(journal.is_interrupted(), "Left for the next run");
+	}
+
+	#[test]
+	fn typed_errors() {
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		let error = "127.0.0.1".parse::<Connection>().unwrap_err();
+		assert!(matches!(error.downcast_ref::<Error>(), Some(Error::Parse { .. })), "Missing port");
+
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let error = command::add::process("127.0.0.1:8081", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap_err();
+		assert!(matches!(error.downcast_ref::<Error>(), Some(Error::DuplicateBridge { .. })), "Host on another bridge");
+		assert_eq!(1, backend.bridges().len(), "No bridge created for duplicate");
+
+		let error = command::remove::process("two.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap_err();
+		assert_eq!(66, error.downcast_ref::<Error>().unwrap().exit_code(), "Not Found");
+
+		let error = Error::PermissionDenied("/etc/hosts".into());
+		assert_eq!(77, error.exit_code());
+		assert!(error.to_string().contains(if cfg!(windows) { "Administrator" } else { "root" }), "Wording of the platform");
+	}
+
+	#[test]
+	fn state_source_of_truth() {
+		let path = scratch_path("state").with_extension("json");
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+
+		// Bridge and host added before the state existed.
+		let bridge = Bridge { listen_to: "127.4.4.4:80".parse().unwrap(), connect_to: "127.0.0.1:8080".parse().unwrap() };
+		backend.add_bridge(bridge.clone()).unwrap();
+		hosts.add(bridge.listen_to.address, "one.test".to_string()).unwrap();
+
+		let mut state = State::load_or_discover(path.clone(), &backend, &hosts).unwrap();
+		assert_eq!(vec!["one.test"], state.find_by_connection(bridge.connect_to).unwrap().hosts, "Discovered existing mapping");
+
+		command::add::process("127.0.0.1:8081", "two.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8081", "a.two.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let reloaded = State::load(path.clone()).unwrap().unwrap();
+		assert_eq!(state.mappings, reloaded.mappings, "State persisted");
+		assert_eq!(2, reloaded.find_by_host("a.two.test").unwrap().hosts.len());
+
+		// Hosts are removed by name from the state, even if the hosts file address was changed by the user.
+		let listen_to = state.find_by_host("two.test").unwrap().listen_to;
+		hosts.delete(crate::DeletionType::Address(listen_to.address)).unwrap();
+		hosts.add("127.9.9.9".parse().unwrap(), "two.test".to_string()).unwrap();
+		hosts.add("127.9.9.9".parse().unwrap(), "a.two.test".to_string()).unwrap();
+
+		command::remove::process("127.0.0.1:8081", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert!(hosts.find_item_by_host("two.test").is_none() && hosts.find_item_by_host("a.two.test").is_none(), "Hosts removed");
+		assert!(!backend.contains(listen_to), "Bridge removed");
+		assert_eq!(1, State::load(path.clone()).unwrap().unwrap().mappings.len(), "Mapping removed");
+
+		std::fs::remove_file(path).ok();
+	}
+
+	#[test]
+	fn journal_recovery() {
+		let path = scratch_path("journal");
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::load(path.clone()).unwrap()).unwrap();
+		assert!(!path.exists(), "Journal removed once committed");
+
+		// Simulate a run which was interrupted after adding the bridge and host.
+		let bridge = backend.bridges()[0].clone();
+		std::fs::write(&path, format!(
+			"done add-bridge {} {}\npending add-host {} one.test\n",
+			bridge.listen_to,
+			bridge.connect_to,
+			bridge.listen_to.address
+		)).unwrap();
+
+		let mut journal = Journal::load(path.clone()).unwrap();
+		assert!(journal.is_interrupted());
+
+		// New transactions are refused until recovered.
+		assert!(command::add::process("127.0.0.1:8081", "two.test", None, &mut backend, &mut hosts, &mut state, &mut journal).is_err());
+
+		journal.recover(&mut backend, &mut hosts).unwrap();
+
+		assert_eq!(0, backend.bridges().len(), "Bridge undone");
+		assert_eq!(0, hosts.items.len(), "Host undone");
+		assert!(!path.exists(), "Journal removed once recovered");
+
+		// Interrupted after committing, the state already has the changes. They're kept.
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		let bridge = backend.bridges()[0].clone();
+
+		std::fs::write(&path, format!(
+			"done add-bridge {} {}\ndone add-host {} one.test\ncommit\n",
+			bridge.listen_to,
+			bridge.connect_to,
+			bridge.listen_to.address
+		)).unwrap();
+
+		Journal::load(path.clone()).unwrap().recover(&mut backend, &mut hosts).unwrap();
+
+		assert_eq!(1, backend.bridges().len(), "Committed bridge kept");
+		assert!(hosts.find_item_by_host("one.test").is_some(), "Committed host kept");
+		assert!(!path.exists(), "Journal removed");
+
+		// Saving the state fails after the journal was marked committed.
+		let state_path = path.with_extension("json");
+		std::fs::write(&state_path, "{ \"mappings\": [] }").unwrap();
+		let mut state = State::load(state_path.clone()).unwrap().unwrap();
+		std::fs::remove_file(&state_path).unwrap();
+		std::fs::create_dir(&state_path).unwrap();
+
+		let mut journal = Journal::load(path.clone()).unwrap();
+		assert!(command::add::process("127.0.0.1:8090", "late.test", None, &mut backend, &mut hosts, &mut state, &mut journal).is_err());
+		assert!(hosts.find_item_by_host("late.test").is_some(), "Not undone once committed");
+		assert!(!journal.is_interrupted() && !path.exists());
+
+		std::fs::remove_dir(&state_path).ok();
+
+		// Undoing only touches the managed line of that address.
+		let store = crate::MemoryStore { contents: "10.0.0.5 one.test\n".to_string() };
+		let mut hosts = HostFile::load(Box::new(store)).unwrap();
+		hosts.add("127.5.5.5".parse().unwrap(), "one.test".to_string()).unwrap();
+		hosts.add("127.6.6.6".parse().unwrap(), "one.test".to_string()).unwrap();
+
+		std::fs::write(&path, "pending add-host 127.6.6.6 one.test\n").unwrap();
+		Journal::load(path.clone()).unwrap().recover(&mut backend, &mut hosts).unwrap();
+
+		let addresses = hosts.items.iter().map(|v| v.address.to_string()).collect::<Vec<_>>();
+		assert_eq!(vec!["10.0.0.5", "127.5.5.5"], addresses, "Other lines of the host kept");
+
+		// Deleted managed lines come back even if the host exists outside of the block.
+		std::fs::write(&path, "done delete-host 127.6.6.6 one.test\ndone delete-host 127.5.5.5 one.test\n").unwrap();
+		Journal::load(path.clone()).unwrap().recover(&mut backend, &mut hosts).unwrap();
+
+		assert!(hosts.items.iter().any(|v| v.managed && v.address.to_string() == "127.6.6.6"), "Restored");
+		assert_eq!(3, hosts.items.len());
+	}
+
+	#[test]
+	fn doctor_fixes_drift() {
+		use command::doctor::{find_issues, Issue};
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8081", "two.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert!(find_issues(&backend, &hosts, &state).is_empty(), "No drift after adding");
+
+		// Bridge with no hosts, host with no bridge and a stored bridge which went missing.
+		let one = backend.bridges()[0].clone();
+		backend.add_bridge(Bridge { listen_to: "127.5.5.5:80".parse().unwrap(), connect_to: "127.0.0.1:9000".parse().unwrap() }).unwrap();
+		hosts.add("127.6.6.6".parse().unwrap(), "orphan.test".to_string()).unwrap();
+		backend.delete_bridge(&one).unwrap();
+
+		let issues = find_issues(&backend, &hosts, &state);
+		assert_eq!(3, issues.len(), "Issues: {:?}", issues);
+		assert!(issues.contains(&Issue::MissingBridge(state.find_by_host("one.test").unwrap().clone())));
+		assert!(issues.iter().any(|v| matches!(v, Issue::OrphanedBridge(b) if b.connect_to.port == 9000)));
+		assert!(issues.iter().any(|v| matches!(v, Issue::OrphanedHost { host, .. } if host == "orphan.test")));
+
+		// Reporting alone changes nothing.
+		command::doctor::process(false, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		assert_eq!(3, find_issues(&backend, &hosts, &state).len());
+
+		command::doctor::process(true, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert!(find_issues(&backend, &hosts, &state).is_empty(), "Drift fixed");
+		assert_eq!(2, backend.bridges().len(), "Bridges Length");
+		assert!(backend.contains(one.listen_to), "Missing bridge recreated with the same address");
+		assert!(hosts.find_item_by_host("orphan.test").is_none(), "Orphaned host removed");
+		assert_eq!(2, state.mappings.len(), "Mappings Length");
+	}
+
+	#[test]
+	fn project_up_down() {
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		let mut project = Project::parse(r#"
+			id = "first"
+
+			[[hosts]]
+			host = "app.test"
+			target = "127.0.0.1:8080"
+
+			[[hosts]]
+			host = "api.test"
+			target = "127.0.0.1:8081"
+		"#).unwrap();
+
+		let other = Project::parse(r#"
+			id = "second"
+
+			[[hosts]]
+			host = "other.test"
+			target = "127.0.0.1:9000"
+		"#).unwrap();
+
+		command::up::process(&project, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::up::process(&other, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		// Applying again changes nothing.
+		command::up::process(&project, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(3, backend.bridges().len(), "Bridges Length");
+		assert_eq!(3, hosts.items.len(), "Hosts Length");
+		assert!(state.find_by_host("app.test").unwrap().is_owned_by("app.test", "first"));
+
+		// Hosts dropped from the file are removed on the next up.
+		project.entries.retain(|v| v.host != "api.test");
+		command::up::process(&project, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(2, backend.bridges().len(), "Bridges Length (Stale removed)");
+		assert!(hosts.find_item_by_host("api.test").is_none());
+
+		command::down::process(&project.id, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(1, backend.bridges().len(), "Only the other project is left");
+		assert_eq!(1, hosts.items.len(), "Hosts Length (Down)");
+		assert!(state.find_by_host("other.test").is_some());
+
+		assert!(Project::parse("[[hosts]]\nhost = \"a.test\"\ntarget = \"nope\"").is_err(), "Invalid target");
+
+		// Hosts sharing a bridge with the project only go down with their owner.
+		command::add::process("127.0.0.1:8080", "mine.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::up::process(&project, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8080", "late.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let mapping = state.find_by_host("app.test").unwrap();
+		assert!(mapping.is_owned_by("app.test", "first"));
+		assert!(!mapping.is_owned_by("mine.test", "first"));
+
+		command::down::process(&project.id, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert!(hosts.find_item_by_host("app.test").is_none(), "Owned host removed");
+		assert!(hosts.find_item_by_host("mine.test").is_some(), "Existing host kept");
+		assert!(hosts.find_item_by_host("late.test").is_some(), "Later host kept");
+		assert!(backend.get_by_connection("127.0.0.1:8080".parse().unwrap()).is_some(), "Bridge kept for the others");
+		assert!(state.find_by_host("mine.test").unwrap().owners.is_empty());
+
+		// A failing host takes the whole project down with it.
+		let failing = Project::parse(r#"
+			id = "failing"
+
+			[[hosts]]
+			host = "first.test"
+			target = "127.0.0.1:7000"
+
+			[[hosts]]
+			host = "other.test"
+			target = "127.0.0.1:7001"
+		"#).unwrap();
+
+		let bridges = backend.bridges().len();
+		let error = command::up::process(&failing, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap_err();
+
+		assert!(matches!(error.downcast_ref::<Error>(), Some(Error::DuplicateBridge { .. })), "Already bridged by the other project");
+		assert_eq!(bridges, backend.bridges().len(), "First bridge undone");
+		assert!(hosts.find_item_by_host("first.test").is_none() && state.find_by_host("first.test").is_none(), "First host undone");
+
+		// Later hosts see the bridges of earlier ones inside the same transaction.
+		let shared = Project::parse("id = \"shared\"\n[[hosts]]\nhost = \"a.test\"\ntarget = \"127.0.0.1:7100\"\n[[hosts]]\nhost = \"b.test\"\ntarget = \"127.0.0.1:7100\"\n").unwrap();
+		command::up::process(&shared, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(bridges + 1, backend.bridges().len(), "One bridge for both");
+		let mapping = state.find_by_host("a.test").unwrap();
+		assert!(mapping.is_owned_by("a.test", "shared") && mapping.is_owned_by("b.test", "shared"));
+
+		// Directories with the same name get different ids.
+		let dir = scratch_path("projects");
+		let ids = ["one", "two"].map(|parent| {
+			let path = dir.join(parent).join("app").join(crate::PROJECT_FILE_NAME);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(&path, "[[hosts]]\nhost = \"a.test\"\ntarget = \"127.0.0.1:8080\"\n").unwrap();
+
+			Project::load(&path).unwrap().id
+		});
+
+		std::fs::remove_dir_all(&dir).ok();
+
+		assert!(ids[0].starts_with("app-") && ids[1].starts_with("app-"));
+		assert_ne!(ids[0], ids[1]);
+	}
+
+	#[test]
+	fn address_allocation() {
+		let add = |state: &mut State, backend: &mut MemoryBackend, address: &str, host: &str| {
+			command::add::process(address, host, None, backend, &mut HostFile::default(), state, &mut Journal::default()).unwrap();
+			state.find_by_host(host).unwrap().listen_to
+		};
+
+		// Hashed addresses are the same every time.
+		let first = add(&mut State::default(), &mut MemoryBackend::default(), "127.0.0.1:8080", "app.test");
+		let second = add(&mut State::default(), &mut MemoryBackend::default(), "127.0.0.1:8080", "app.test");
+		assert_eq!(first, second, "Deterministic");
+
+		// Sequential starts at the beginning of the subnet.
+		let mut state = State::default();
+		state.allocator = Allocator { strategy: Strategy::Sequential, subnet: "127.1.0.0/30".parse().unwrap() };
+
+		let mut backend = MemoryBackend::default();
+		assert_eq!("127.1.0.1:80", add(&mut state, &mut backend, "127.0.0.1:8080", "one.test").to_string());
+		assert_eq!("127.1.0.2:80", add(&mut state, &mut backend, "127.0.0.1:8081", "two.test").to_string());
+		assert_eq!("127.1.0.3:80", add(&mut state, &mut backend, "127.0.0.1:8082", "three.test").to_string());
+
+		// Every usable address of the /30 is taken.
+		let error = command::add::process("127.0.0.1:8083", "four.test", None, &mut backend, &mut HostFile::default(), &mut state, &mut Journal::default()).unwrap_err();
+		assert_eq!(69, error.downcast_ref::<Error>().unwrap().exit_code(), "Exhausted");
+
+		// A removed host gets its' previous address back.
+		state.allocator.strategy = Strategy::Random;
+		state.allocator.subnet = Subnet::default();
+
+		let mut hosts = HostFile::default();
+		command::add::process("127.0.0.1:9000", "again.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		let previous = state.find_by_host("again.test").unwrap().listen_to;
+		command::remove::process("again.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:9000", "again.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		assert_eq!(previous, state.find_by_host("again.test").unwrap().listen_to, "Reused");
+
+		// The settings are stored with the state, so they only have to be given once.
+		let path = scratch_path("allocator").with_extension("json");
+		let mut state = State::load_or_discover(path.clone(), &MemoryBackend::default(), &HostFile::default()).unwrap();
+		state.allocator = Allocator { strategy: Strategy::Sequential, subnet: "127.1.0.0/30".parse().unwrap() };
+		state.save().unwrap();
+
+		assert_eq!(state.allocator, State::load(path.clone()).unwrap().unwrap().allocator, "Persisted");
+
+		std::fs::write(&path, "{ \"mappings\": [] }").unwrap();
+		assert_eq!(Allocator::default(), State::load(path.clone()).unwrap().unwrap().allocator, "Defaults for older states");
+		std::fs::remove_file(path).ok();
+
+		assert!("10.0.0.0/8".parse::<Subnet>().is_err(), "Not loopback");
+		assert!("127.0.0.0/31".parse::<Subnet>().is_err(), "Too small");
+		assert!("127.0.0.0/24".parse::<Subnet>().is_err(), "Only addresses which are always skipped");
+		assert!("127.0.0.0/16".parse::<Subnet>().is_ok());
+	}
+
+	#[test]
+	fn listen_ports() {
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		command::add::process("127.0.0.1:8080", "app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8443", "app.test", Some(443), &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8080", "www.app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(2, backend.bridges().len(), "Bridges Length");
+		assert_eq!(2, hosts.items.len(), "One hosts line per host");
+
+		let ports = state.filter_by_host("app.test").map(|v| v.listen_to.port).collect::<Vec<_>>();
+		assert_eq!(vec![80, 443], ports, "Listening on both ports");
+		assert_eq!(backend.bridges()[0].listen_to.address, backend.bridges()[1].listen_to.address, "Ports share the hosts' address");
+		assert_eq!(2, state.find_target(&Target::parse("www.app.test").unwrap()).len(), "Hosts on the address are served by every port");
+
+		let routes = command::serve::create_routes(&backend, &state);
+		assert_eq!("127.0.0.1:8443".parse::<Connection>().unwrap(), routes[&("app.test".to_string(), 443)]);
+		assert_eq!(4, routes.len(), "Routes Length");
+
+		// Port is already used by another target.
+		assert!(command::add::process("127.0.0.1:9443", "app.test", Some(443), &mut backend, &mut hosts, &mut state, &mut Journal::default()).is_err());
+		// Target is already listening on another port.
+		assert!(command::add::process("127.0.0.1:8443", "other.test", Some(8443), &mut backend, &mut hosts, &mut state, &mut Journal::default()).is_err());
+
+		command::remove::process("app.test:443", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(1, backend.bridges().len(), "Bridges Length (Port removed)");
+		assert_eq!(2, hosts.items.len(), "Hosts still use port 80");
+
+		command::remove::process("127.0.0.1:8080", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		assert_eq!(0, backend.bridges().len(), "Bridges Length (Removed)");
+		assert_eq!(0, hosts.items.len(), "Hosts Length (Removed)");
+		assert!(state.mappings.is_empty());
+	}
+
+	#[test]
+	fn output_formats() {
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		let added = command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		let listen_to = added.listen_to;
+
+		let json = serde_json::to_value(&added).unwrap();
+		assert_eq!("add-bridge", json["changes"][0]["action"], "Bridge added first");
+		assert_eq!("127.0.0.1:8080", json["changes"][0]["connect_to"]);
+		assert_eq!("add-host", json["changes"][1]["action"]);
+		assert_eq!(format!("Added 127.0.0.1:8080 to new Bridge for {:?}.", "one.test"), Format::Table.render(&added).unwrap());
+
+		// Adding again changes nothing.
+		let added = command::add::process("127.0.0.1:8080", "one.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		assert!(added.changes.is_empty());
+
+		let listing = command::list::process(&backend, &state).unwrap();
+		let json: serde_json::Value = serde_json::from_str(&Format::Json.render(&listing).unwrap()).unwrap();
+		assert_eq!(listen_to.to_string(), json[0]["listen_to"]);
+		assert_eq!("active", json[0]["status"]);
+		assert_eq!(serde_json::json!(["one.test"]), json[0]["hosts"]);
+
+		let yaml = Format::Yaml.render(&listing).unwrap();
+		assert!(yaml.contains("status: active"), "{}", yaml);
+
+		let table = Format::Table.render(&listing).unwrap();
+		assert!(table.starts_with("TARGET"), "{}", table);
+		assert!(table.contains("one.test"), "{}", table);
+
+		let removed = command::remove::process("one.test", &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		let json = serde_json::to_value(&removed).unwrap();
+		assert_eq!("delete-host", json["changes"][0]["action"]);
+		assert_eq!("delete-bridge", json["changes"][1]["action"]);
+
+		assert!("xml".parse::<Format>().is_err());
+	}
+
+	#[test]
+	fn manager_facade() {
+		let mut manager = crate::Manager::in_memory();
+
+		let added = manager.add("127.0.0.1:8080", "one.test", None).unwrap();
+		manager.add("127.0.0.1:8080", "a.one.test", None).unwrap();
+
+		assert_eq!(added.listen_to, manager.backend().bridges()[0].listen_to);
+		assert_eq!(2, manager.hosts().items.len());
+
+		let listing = manager.list().unwrap();
+		assert_eq!(1, listing.0.len());
+		assert_eq!(vec!["one.test", "a.one.test"], listing.0[0].hosts);
+
+		let testing = manager.test("one.test").unwrap();
+		assert_eq!("127.0.0.1:8080".parse::<Connection>().unwrap(), testing.listening[0].connect_to);
+
+		assert!(manager.doctor(false).unwrap().issues.is_empty());
+
+		manager.remove("127.0.0.1:8080").unwrap();
+
+		assert!(manager.list().unwrap().0.is_empty());
+		assert!(manager.restore(None).is_err(), "No backups in memory");
+	}
+
+	#[test]
+	fn dry_run_plans() {
+		use crate::{Manager, UserspaceProxy, diff_lines};
+
+		let path = scratch_path("plan");
+		let backend = UserspaceProxy::load(path.clone()).unwrap();
+		let mut manager = Manager::new(Box::new(backend), HostFile::default(), State::default(), Journal::default());
+
+		assert_eq!(vec!["-b", "+c"], diff_lines("a\nb\n", "a\nc\n"));
+
+		// Large unchanged files (ad-block lists) only compare what changed.
+		let blocked = (0..200_000).map(|i| format!("0.0.0.0 ad{}.test\n", i)).collect::<String>();
+		assert_eq!(vec!["+127.0.0.2 one.test"], diff_lines(&format!("{}end\n", blocked), &format!("{}127.0.0.2 one.test\nend\n", blocked)));
+		assert_eq!(vec!["-x", "-y"], diff_lines("x\ny\n", ""));
+
+		let plan = manager.plan_add("127.0.0.1:8080", "one.test", None).unwrap();
+
+		assert_eq!(2, plan.changes.len());
+		assert!(plan.hosts_diff.iter().any(|v| v.starts_with('+') && v.ends_with("one.test")));
+		assert!(plan.commands[0].starts_with(&format!("write {}", path.display())));
+		assert!(plan.commands[0].ends_with(" 127.0.0.1:8080\n"));
+
+		// Nothing was changed.
+		assert!(manager.backend().bridges().is_empty());
+		assert!(manager.hosts().items.is_empty());
+		assert!(manager.state().mappings.is_empty());
+		assert!(!path.exists());
+
+		manager.add("127.0.0.1:8080", "one.test", None).unwrap();
+
+		let plan = manager.plan_remove("one.test").unwrap();
+		assert!(plan.hosts_diff.iter().any(|v| v.starts_with('-') && v.ends_with("one.test")));
+		assert!(plan.commands[0].ends_with(":\n"), "Bridges file emptied");
+		assert_eq!(1, manager.backend().bridges().len());
+
+		let project = Project::parse("id = \"app\"\n[[hosts]]\nhost = \"app.test\"\ntarget = \"127.0.0.1:3000\"\n").unwrap();
+		let plan = manager.plan_up(&project).unwrap();
+		assert!(plan.changes.iter().any(|v| matches!(v, crate::Change::AddHost { host, .. } if host == "app.test")));
+		assert!(manager.state().mappings.iter().all(|v| v.owners.is_empty()));
+
+		// Drift is planned to be fixed without fixing it.
+		manager.hosts_mut().delete(crate::DeletionType::Host("one.test")).unwrap();
+		assert!(manager.plan_doctor(false).unwrap().hosts_diff.is_empty(), "Only planned if asked to fix");
+		let plan = manager.plan_doctor(true).unwrap();
+		assert!(plan.hosts_diff.iter().any(|v| v.starts_with('+') && v.ends_with("one.test")));
+		assert!(manager.hosts().items.is_empty());
+
+		assert!(Format::Json.render(&plan).unwrap().contains("\"hosts_diff\""));
+
+		// Interrupted runs are only undone when asked to. Planning refuses until then.
+		let journal_path = path.with_extension("journal");
+		std::fs::write(&journal_path, "done add-host 127.0.0.9 two.test\n").unwrap();
+
+		let mut hosts = HostFile::default();
+		hosts.add("127.0.0.9".parse().unwrap(), "two.test".to_string()).unwrap();
+
+		let mut manager = Manager::new(Box::new(MemoryBackend::default()), hosts, State::default(), Journal::load(journal_path.clone()).unwrap());
+
+		assert!(manager.plan_add("127.0.0.1:8081", "three.test", None).is_err());
+		assert_eq!(1, manager.hosts().items.len(), "Nothing undone while planning");
+
+		assert!(manager.recover().unwrap());
+		assert!(manager.hosts().items.is_empty(), "Undone");
+		assert!(!manager.recover().unwrap(), "Nothing left to undo");
+
+		std::fs::remove_file(path).ok();
+		std::fs::remove_file(journal_path).ok();
+	}
+
+	#[test]
+	fn dns_responder() {
+		use std::{io::{Read, Write}, net::{IpAddr, TcpStream, UdpSocket}, time::Duration};
+		use crate::{DnsServer, RecordType, Zone, query, parse_response, RCODE_NO_ERROR, RCODE_REFUSED};
+
+		let mut manager = crate::Manager::in_memory();
+		let app = manager.add("127.0.0.1:8080", "app.test", None).unwrap().listen_to.address;
+		manager.add("127.0.0.1:8080", "*.app.test", None).unwrap();
+		let admin = manager.add("127.0.0.1:8081", "*.admin.app.test", None).unwrap().listen_to.address;
+
+		let mut zone = Zone::from_state(manager.state());
+		zone.records.push(("v6.test".to_string(), "::1".parse().unwrap()));
+
+		assert_eq!(Some(vec![app]), zone.lookup("APP.test.", RecordType::A));
+		assert_eq!(Some(vec![app]), zone.lookup("tenant.app.test", RecordType::A), "Wildcard");
+		assert_eq!(Some(vec![admin]), zone.lookup("a.admin.app.test", RecordType::A), "Longest wildcard wins");
+		assert_eq!(Some(vec![]), zone.lookup("v6.test", RecordType::A), "No IPv4 address");
+		assert_eq!(None, zone.lookup("other.test", RecordType::A));
+		assert_eq!(None, zone.lookup("app.test.evil", RecordType::A));
+
+		// Upstream only knows "other.test".
+		let upstream = DnsServer::bind(Zone { records: vec![("other.test".to_string(), "127.9.9.9".parse().unwrap())] }, "127.0.0.1:0".parse().unwrap(), None).unwrap();
+		let upstream_addr = upstream.local_addr().unwrap();
+		upstream.spawn();
+
+		let refusing = DnsServer::bind(zone.clone(), "127.0.0.1:0".parse().unwrap(), None).unwrap();
+		let refusing_addr = refusing.local_addr().unwrap();
+		refusing.spawn();
+
+		let forwarding = DnsServer::bind(zone, "127.0.0.1:0".parse().unwrap(), Some(upstream_addr.into())).unwrap();
+		let forwarding_addr = forwarding.local_addr().unwrap();
+		forwarding.spawn();
+
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+		let ask = |server, id, name: &str, record_type| {
+			socket.send_to(&query(id, name, record_type), server).unwrap();
+
+			let mut buffer = [0; 512];
+			let len = socket.recv(&mut buffer).unwrap();
+
+			assert_eq!(id.to_be_bytes(), buffer[..2], "Same id");
+			parse_response(&buffer[..len]).unwrap()
+		};
+
+		assert_eq!((RCODE_NO_ERROR, vec![app]), ask(refusing_addr, 1, "x.app.test", RecordType::A));
+		assert_eq!((RCODE_NO_ERROR, vec!["::1".parse::<IpAddr>().unwrap()]), ask(refusing_addr, 2, "v6.test", RecordType::Aaaa));
+		assert_eq!((RCODE_NO_ERROR, vec![]), ask(refusing_addr, 3, "app.test", RecordType::Aaaa));
+		assert_eq!(RCODE_REFUSED, ask(refusing_addr, 4, "other.test", RecordType::A).0);
+
+		assert_eq!((RCODE_NO_ERROR, vec!["127.9.9.9".parse::<IpAddr>().unwrap()]), ask(forwarding_addr, 5, "other.test", RecordType::A), "Forwarded");
+		assert_eq!((RCODE_NO_ERROR, vec![app]), ask(forwarding_addr, 6, "app.test", RecordType::A));
+
+		// TCP messages are prefixed with their length.
+		let mut stream = TcpStream::connect(refusing_addr).unwrap();
+		let packet = query(7, "deep.tenant.app.test", RecordType::A);
+		stream.write_all(&(packet.len() as u16).to_be_bytes()).unwrap();
+		stream.write_all(&packet).unwrap();
+
+		let mut len = [0; 2];
+		stream.read_exact(&mut len).unwrap();
+		let mut reply = vec![0; u16::from_be_bytes(len) as usize];
+		stream.read_exact(&mut reply).unwrap();
+
+		assert_eq!((RCODE_NO_ERROR, vec![app]), parse_response(&reply).unwrap());
+
+		assert!(DnsServer::bind(Zone::default(), "0.0.0.0:0".parse().unwrap(), None).is_err(), "Loopback only");
+		assert!(manager.dns("10.0.0.1:53".parse().unwrap(), None).is_err());
+
+		// A slow upstream doesn't hold up local answers.
+		let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let stalled = DnsServer::bind(Zone::from_state(manager.state()), "127.0.0.1:0".parse().unwrap(), Some(silent.local_addr().unwrap().into())).unwrap();
+		let stalled_addr = stalled.local_addr().unwrap();
+		stalled.spawn();
+
+		// Its' own socket since the forwarded query fails later on.
+		let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+		client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+		let started = std::time::Instant::now();
+		client.send_to(&query(8, "other.test", RecordType::A), stalled_addr).unwrap();
+		client.send_to(&query(9, "app.test", RecordType::A), stalled_addr).unwrap();
+
+		let mut buffer = [0; 512];
+		client.recv(&mut buffer).unwrap();
+		assert_eq!(9u16.to_be_bytes(), buffer[..2], "Local answer first");
+		assert!(started.elapsed() < Duration::from_secs(2));
+
+		// Wildcards are only kept in the state.
+		assert!(!manager.hosts().document().to_string().contains("*.app.test"));
+		assert!(manager.hosts().find_item_by_host("app.test").is_some());
+
+		manager.remove("*.app.test").unwrap();
+		assert!(manager.state().find_by_host("*.app.test").is_none());
+		assert!(manager.state().find_by_host("app.test").is_some());
+
+		// Hosts added while running are answered once the state changes.
+		let path = scratch_path("dns-state").with_extension("json");
+		std::fs::write(&path, "{ \"mappings\": [] }").unwrap();
+
+		let mut state = State::load(path.clone()).unwrap().unwrap();
+		let watching = DnsServer::bind(Zone::from_state(&state), "127.0.0.1:0".parse().unwrap(), None).unwrap().watch(path.clone());
+		let watching_addr = watching.local_addr().unwrap();
+		watching.spawn();
+
+		assert_eq!(RCODE_REFUSED, ask(watching_addr, 10, "late.test", RecordType::A).0);
+
+		let late = command::add::process("127.0.0.1:9000", "late.test", None, &mut MemoryBackend::default(), &mut HostFile::default(), &mut state, &mut Journal::default()).unwrap().listen_to.address;
+
+		let reloaded = (11..60).any(|id| {
+			std::thread::sleep(Duration::from_millis(100));
+			ask(watching_addr, id, "late.test", RecordType::A) == (RCODE_NO_ERROR, vec![late])
+		});
+
+		std::fs::remove_file(path).ok();
+		assert!(reloaded, "Zone reloaded");
+	}
+
+	#[test]
+	fn certificate_authority() {
+		use crate::{CertStore, command::cert::{CertStatus, Encoding}};
+
+		let dir = scratch_path("certs");
+		let store = CertStore::new(dir.clone());
+		let now = time::OffsetDateTime::now_utc();
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		hosts.group_hosts = true;
+		command::add::process("127.0.0.1:8080", "app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8080", "www.app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8081", "api.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process("127.0.0.1:8080", "*.app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let certified = command::cert::process(&hosts, &state, &store, None, now).unwrap();
+
+		assert!(certified.root_created);
+		assert_eq!(3, certified.certs.len());
+		assert_eq!(vec!["app.test", "www.app.test"], certified.certs[0].names, "Aliases are subject alternative names");
+		assert_eq!(vec!["*.app.test"], certified.certs[2].names, "Wildcards from the state");
+		assert!(certified.certs.iter().all(|v| v.status == CertStatus::Issued && v.cert_path.exists() && v.key_path.exists()));
+
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(&certified.certs[0].key_path).unwrap().permissions().mode();
+			assert_eq!(0o600, mode & 0o777, "Private key only readable by the user");
+		}
+
+		// Nothing changed.
+		let certified = command::cert::process(&hosts, &state, &store, None, now).unwrap();
+		assert!(!certified.root_created);
+		assert!(certified.certs.iter().all(|v| v.status == CertStatus::Valid));
+
+		// Renewed once it's about to expire.
+		let later = now + time::Duration::days(crate::LEAF_VALIDITY_DAYS - crate::RENEW_BEFORE_DAYS + 1);
+		let certified = command::cert::process(&hosts, &state, &store, None, later).unwrap();
+		assert!(certified.certs.iter().all(|v| v.status == CertStatus::Renewed));
+		assert!(certified.certs[0].not_after > (later + time::Duration::days(crate::RENEW_BEFORE_DAYS)).unix_timestamp());
+
+		// Renewed once the names change.
+		command::add::process("127.0.0.1:8081", "www.api.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		let certified = command::cert::process(&hosts, &state, &store, None, later).unwrap();
+		assert_eq!(CertStatus::Renewed, certified.certs[1].status);
+		assert_eq!(vec!["api.test", "www.api.test"], certified.certs[1].names);
+
+		let (pem, der) = (dir.join("export.pem"), dir.join("export.der"));
+		command::cert::process(&hosts, &state, &store, Some((&pem, Encoding::Pem)), later).unwrap();
+		command::cert::process(&hosts, &state, &store, Some((&der, Encoding::Der)), later).unwrap();
+
+		assert!(std::fs::read_to_string(pem).unwrap().starts_with("-----BEGIN CERTIFICATE-----"));
+		assert_eq!(store.root_der().unwrap(), std::fs::read(der).unwrap());
+
+		assert!(crate::Manager::in_memory().cert(None).is_err(), "No certificate store");
+
+		std::fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn tls_termination() {
+		use std::{io::{BufReader, Read, Write}, net::TcpStream, sync::Arc, thread, time::Duration};
+		use crate::{CertResolver, CertStore, command::probe::wait_for};
+
+		let dir = scratch_path("tls");
+		let store = CertStore::new(dir.clone());
+
+		let target = format!("127.0.0.1:{}", free_port());
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		hosts.group_hosts = true;
+		command::add::process(&target, "app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process(&target, "*.app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process(&target, "Admin.Test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		command::cert::process(&hosts, &state, &store, None, time::OffsetDateTime::now_utc()).unwrap();
+
+		let resolver = CertResolver::load(&store, &hosts, &state).unwrap();
+		assert!(resolver.find("APP.test").is_some());
+		assert!(resolver.find("admin.test").is_some(), "Mixed case names");
+		assert!(resolver.find("tenant.app.test").is_some(), "Wildcard");
+		assert!(resolver.find("a.tenant.app.test").is_none(), "Wildcards match a single label");
+		assert!(resolver.find("other.test").is_none());
+
+		assert!(command::test::process(&target, &state).unwrap().with_tls(CertResolver::default()).is_err(), "Missing certificates");
+
+		let testing = command::test::process(&target, &state).unwrap().with_tls(resolver).unwrap();
+		assert!(testing.tls);
+		thread::spawn(move || testing.run());
+		assert!(wait_for(target.parse().unwrap(), Duration::from_secs(5)));
+
+		let mut config = rustls::ClientConfig::new();
+		config.root_store.add_pem_file(&mut BufReader::new(std::fs::File::open(store.root_path()).unwrap())).unwrap();
+		let config = Arc::new(config);
+
+		let get = |name: &str| -> std::io::Result<String> {
+			let tcp = TcpStream::connect(&target)?;
+
+			let session = rustls::ClientSession::new(&config, webpki::DNSNameRef::try_from_ascii_str(name).unwrap());
+			let mut stream = rustls::StreamOwned::new(session, tcp);
+
+			write!(stream, "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", name)?;
+
+			let mut response = Vec::new();
+			stream.read_to_end(&mut response).ok();
+
+			if response.is_empty() {
+				return Err(std::io::Error::other("Handshake failed"));
+			}
+
+			Ok(String::from_utf8_lossy(&response).to_string())
+		};
+
+		let response = get("app.test").unwrap();
+		assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+		assert!(response.contains("Viewing Host \"app.test\""));
+
+		assert!(get("other.test").is_err(), "No certificate for the name");
+
+		std::fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	#[ignore = "listens on ports 80 and 443, which needs root"]
+	fn tls_only_on_https_port() {
+		use std::{io::{BufReader, Read, Write}, net::TcpStream, sync::Arc, thread, time::Duration};
+		use crate::{CertResolver, CertStore, command::probe::wait_for};
+
+		let dir = scratch_path("serve-tls");
+		let store = CertStore::new(dir.clone());
+
+		let (http_target, https_target) = (format!("127.0.0.1:{}", free_port()), format!("127.0.0.1:{}", free_port()));
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		command::add::process(&http_target, "app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process(&https_target, "app.test", Some(443), &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::cert::process(&hosts, &state, &store, None, time::OffsetDateTime::now_utc()).unwrap();
+
+		let serving = command::serve::process(&backend, &state).unwrap()
+			.with_tls(CertResolver::load(&store, &hosts, &state).unwrap())
+			.unwrap();
+
+		let table = crate::Format::Table.render(&serving).unwrap();
+		assert!(table.contains("http://app.test:80"), "{}", table);
+		assert!(table.contains("https://app.test:443"), "{}", table);
+
+		let address = serving.listening[0].address;
+
+		for target in [&http_target, &https_target] {
+			let testing = command::test::process(target, &state).unwrap();
+			thread::spawn(move || testing.run());
+			assert!(wait_for(target.parse().unwrap(), Duration::from_secs(5)));
+		}
+
+		thread::spawn(move || serving.run());
+		assert!(wait_for((address, 443).into(), Duration::from_secs(5)));
+
+		let request = "GET / HTTP/1.1\r\nHost: app.test\r\nConnection: close\r\n\r\n";
+
+		// Plain HTTP still works on 80.
+		let mut stream = TcpStream::connect((address, 80)).unwrap();
+		stream.write_all(request.as_bytes()).unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).ok();
+		assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+		let mut config = rustls::ClientConfig::new();
+		config.root_store.add_pem_file(&mut BufReader::new(std::fs::File::open(store.root_path()).unwrap())).unwrap();
+
+		let session = rustls::ClientSession::new(&Arc::new(config), webpki::DNSNameRef::try_from_ascii_str("app.test").unwrap());
+		let mut stream = rustls::StreamOwned::new(session, TcpStream::connect((address, 443)).unwrap());
+		stream.write_all(request.as_bytes()).unwrap();
+		let mut response = Vec::new();
+		stream.read_to_end(&mut response).ok();
+		let response = String::from_utf8_lossy(&response);
+		assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+		assert!(response.contains("Viewing Host"), "{}", response);
+
+		std::fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn proxy_forwarding() {
+		use std::{io::{Read, Write}, net::TcpStream, thread, time::Duration};
+		use crate::{Mock, command::probe::wait_for};
+
+		let target = format!("127.0.0.1:{}", free_port());
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		let listen_to = command::add::process(&target, "app.test", Some(free_port()), &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap().listen_to;
+		command::add::process(&target, "Admin.Test", Some(listen_to.port), &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process(&target, "*.app.test", Some(listen_to.port), &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let testing = command::test::process(&target, &state).unwrap().with_mock(None, Mock::Echo).unwrap();
+		thread::spawn(move || testing.run());
+		assert!(wait_for(target.parse().unwrap(), Duration::from_secs(5)));
+
+		let serving = command::serve::process(&backend, &state).unwrap();
+		thread::spawn(move || serving.run());
+		assert!(wait_for(listen_to, Duration::from_secs(5)));
+
+		let send = |raw: String| {
+			let mut stream = TcpStream::connect(std::net::SocketAddr::from(listen_to)).unwrap();
+			stream.write_all(raw.as_bytes()).unwrap();
+
+			let mut response = String::new();
+			stream.read_to_string(&mut response).unwrap();
+			response
+		};
+
+		let response = send(format!(
+			"POST /items?id=3 HTTP/1.1\r\nHost: APP.Test:{}\r\nX-Test: 1\r\nKeep-Alive: timeout=5\r\nProxy-Authorization: Basic c2VjcmV0\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+			listen_to.port
+		));
+
+		assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+		// Streamed back chunked.
+		let json: serde_json::Value = serde_json::from_str(&response[response.find('{').unwrap()..=response.rfind('}').unwrap()]).unwrap();
+
+		assert_eq!("POST", json["method"]);
+		assert_eq!("/items", json["path"]);
+		assert_eq!("id=3", json["query"]);
+		assert_eq!("hello", json["body"]);
+		assert_eq!(format!("APP.Test:{}", listen_to.port), json["host"], "Matched case insensitively and passed on as is");
+		assert_eq!("1", json["headers"]["x-test"]);
+		assert_eq!("http", json["headers"]["x-forwarded-proto"]);
+		assert!(json["headers"]["keep-alive"].is_null(), "Hop-by-hop headers dropped");
+		assert!(json["headers"]["proxy-authorization"].is_null(), "Hop-by-hop headers dropped");
+
+		let mixed = send("GET / HTTP/1.1\r\nHost: admin.test\r\nConnection: close\r\n\r\n".to_string());
+		assert!(mixed.starts_with("HTTP/1.1 200"), "Mixed case hosts file names: {}", mixed);
+
+		let tenant = send("GET / HTTP/1.1\r\nHost: tenant.app.test\r\nConnection: close\r\n\r\n".to_string());
+		assert!(tenant.starts_with("HTTP/1.1 200"), "Wildcards from the state: {}", tenant);
+
+		let unknown = send("GET / HTTP/1.1\r\nHost: [::1]:80\r\nConnection: close\r\n\r\n".to_string());
+		assert!(unknown.starts_with("HTTP/1.1 404"), "{}", unknown);
+		assert!(unknown.contains("Unknown Host \"[::1]\""), "Port split off after the brackets");
+	}
+
+	#[test]
+	fn mock_server() {
+		use std::{collections::BTreeMap, fs, io::{Read, Write}, net::TcpStream, thread, time::Duration};
+		use crate::{Mock, MockRequest, MockRoute, command::probe::wait_for};
+
+		let dir = scratch_path("mock");
+		fs::create_dir_all(dir.join("public/docs")).unwrap();
+		fs::write(dir.join("public/index.html"), "<h1>Home</h1>").unwrap();
+		fs::write(dir.join("public/docs/app.js"), "run()").unwrap();
+		fs::write(dir.join("secret"), "secret").unwrap();
+		fs::write(dir.join("routes.toml"), "[[routes]]\nmethod = \"POST\"\npath = \"/users\"\nstatus = 201\nbody = '{ \"id\": 1 }'\nheaders = { Content-Type = \"application/json\" }\n\n[[routes]]\npath = \"/users/*\"\nbody = \"user\"\n").unwrap();
+
+		let request = |method: &str, path: &str| MockRequest {
+			method: method.to_string(),
+			host: "app.test".to_string(),
+			path: path.to_string(),
+			query: String::new(),
+			headers: BTreeMap::new(),
+			body: String::new()
+		};
+
+		let files = Mock::parse(&format!("static:{}", dir.join("public").display())).unwrap();
+		assert_eq!(b"<h1>Home</h1>".to_vec(), files.respond(&request("GET", "/")).body);
+		assert_eq!(("Content-Type".to_string(), "text/javascript".to_string()), files.respond(&request("GET", "/docs/app.js")).headers[0]);
+		assert_eq!(404, files.respond(&request("GET", "/../secret")).status, "Never leaves the directory");
+		assert_eq!(404, files.respond(&request("GET", "/missing.html")).status);
+
+		let routes = Mock::parse(&format!("routes:{}", dir.join("routes.toml").display())).unwrap();
+		let created = routes.respond(&request("POST", "/users"));
+		assert_eq!((201, b"{ \"id\": 1 }".to_vec()), (created.status, created.body));
+		assert_eq!(b"user".to_vec(), routes.respond(&request("DELETE", "/users/1")).body, "Any method");
+		assert_eq!(404, routes.respond(&request("GET", "/users")).status);
+
+		assert!(MockRoute::parse("[[routes]]\nstatus = 200").is_err(), "Path is required");
+		assert!(Mock::parse("static:/does/not/exist").is_err());
+		assert!(Mock::parse("proxy").is_err());
+
+		// Served for every method.
+		let target = format!("127.0.0.1:{}", free_port());
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		command::add::process(&target, "app.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		command::add::process(&target, "api.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+
+		let testing = command::test::process(&target, &state).unwrap()
+			.with_mock(None, Mock::Echo).unwrap()
+			.with_mock(Some("app.test"), files).unwrap();
+
+		assert!(command::test::process(&target, &state).unwrap().with_mock(Some("other.test"), Mock::Echo).is_err());
+		assert_eq!(Some(&Mock::Echo), testing.mocks.get("api.test"));
+
+		thread::spawn(move || testing.run());
+		assert!(wait_for(target.parse().unwrap(), Duration::from_secs(5)));
+
+		let send = |raw: String| {
+			let mut stream = TcpStream::connect(&target).unwrap();
+
+			stream.write_all(raw.as_bytes()).unwrap();
+
+			let mut response = String::new();
+			stream.read_to_string(&mut response).unwrap();
+			response
+		};
+
+		let echoed = send("PUT /items?id=3 HTTP/1.1\r\nHost: api.test\r\nX-Test: 1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_string());
+		let json: serde_json::Value = serde_json::from_str(&echoed[echoed.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+
+		assert_eq!("PUT", json["method"]);
+		assert_eq!("/items", json["path"]);
+		assert_eq!("id=3", json["query"]);
+		assert_eq!("1", json["headers"]["x-test"]);
+		assert_eq!("hello", json["body"]);
+
+		let page = send("GET / HTTP/1.1\r\nHost: app.test\r\nConnection: close\r\n\r\n".to_string());
+		assert!(page.ends_with("<h1>Home</h1>"), "Selected per host");
+
+		let unknown = send("GET / HTTP/1.1\r\nHost: other.test\r\nConnection: close\r\n\r\n".to_string());
+		assert!(unknown.starts_with("HTTP/1.1 404"));
+
+		fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn connection_probe() {
+		use std::{io::{Read, Write}, net::{IpAddr, TcpListener}, thread, time::Duration};
+		use crate::command::probe::{HopKind, wait_for};
+
+		let target = format!("127.0.0.1:{}", free_port());
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		let listen_to = command::add::process(&target, "app.test", Some(free_port()), &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap().listen_to;
+
+		let resolve = move |host: &str| -> std::io::Result<Vec<IpAddr>> {
+			match host {
+				"app.test" => Ok(vec![listen_to.address]),
+				_ => Err(std::io::Error::other("Unknown host"))
+			}
+		};
+
+		// Nothing is running yet.
+		let probed = command::probe::process("app.test", &state, &resolve).unwrap();
+		let results = probed.0[0].hops.iter().map(|v| (v.kind, v.passed)).collect::<Vec<_>>();
+
+		assert_eq!(vec![(HopKind::Resolve, true), (HopKind::Listen, false), (HopKind::Target, false)], results);
+		assert!(!probed.passed());
+
+		// Bridge forwarding to the target. Only answers, the memory backend doesn't forward anything.
+		let bridge = TcpListener::bind(std::net::SocketAddr::from(listen_to)).unwrap();
+		thread::spawn(move || {
+			for mut stream in bridge.incoming().flatten() {
+				// Wait for the request before answering.
+				let mut buffer = [0; 256];
+				let _ = stream.read(&mut buffer);
+				stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").ok();
+			}
+		});
+
+		let testing = command::test::process(&target, &state).unwrap();
+		thread::spawn(move || testing.run());
+		assert!(wait_for(target.parse().unwrap(), Duration::from_secs(5)));
+
+		let probed = command::probe::process(&target, &state, &resolve).unwrap();
+		assert!(probed.passed(), "{:?}", probed);
+		assert!(probed.0[0].hops[1].detail.contains("204"));
+		assert!(probed.0[0].hops[2].detail.contains("200"));
+
+		// Resolves elsewhere.
+		let elsewhere = |_: &str| -> std::io::Result<Vec<IpAddr>> { Ok(vec!["127.0.0.1".parse().unwrap()]) };
+		let probed = command::probe::process("app.test", &state, &elsewhere).unwrap();
+		assert!(!probed.0[0].hops[0].passed);
+		assert!(probed.0[0].hops[0].detail.contains("expected"));
+
+		assert!(command::probe::process("other.test", &state, &resolve).is_err());
+	}
+
+	#[test]
+	fn port_status() {
+		use std::net::TcpListener;
+		use crate::PortStatus;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let used: Connection = listener.local_addr().unwrap().into();
+		let free: Connection = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+
+		assert_eq!(PortStatus::NotListening, free.status());
+
+		// The test process owns the socket so it can always be found on Linux.
+		match used.status() {
+			PortStatus::Listening { owner } => {
+				if cfg!(target_os = "linux") {
+					assert_eq!(Some(std::process::id()), owner.map(|v| v.pid));
+				}
+			}
+			PortStatus::NotListening => panic!("{} should be listening", used)
+		}
+
+		let (mut backend, mut hosts) = (MemoryBackend::default(), HostFile::default());
+		let mut state = State::default();
+
+		// Nothing is listening on the target yet.
+		let added = command::add::process(&free.to_string(), "free.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		assert_eq!(PortStatus::NotListening, added.target);
+		assert!(Format::Table.render(&added).unwrap().contains("Nothing is listening"));
+
+		let added = command::add::process(&used.to_string(), "used.test", None, &mut backend, &mut hosts, &mut state, &mut Journal::default()).unwrap();
+		assert!(added.target.is_listening());
+
+		let listing = command::list::process(&backend, &state).unwrap();
+		let statuses = listing.0.iter().map(|v| (v.connect_to, v.target.is_listening())).collect::<Vec<_>>();
+		assert!(statuses.contains(&(free, false)));
+		assert!(statuses.contains(&(used, true)));
+		assert!(Format::Table.render(&listing).unwrap().contains("LISTENER"));
+
+		// The test server refuses to start instead of failing to bind.
+		let testing = command::test::process("used.test", &state).unwrap();
+		assert!(testing.listening[0].status.is_listening());
+
+		let error = testing.run().unwrap_err();
+		assert!(matches!(error.downcast_ref::<Error>(), Some(Error::PortInUse { connection, .. }) if *connection == used));
+
+		drop(listener);
+		assert!(command::test::process("used.test", &state).unwrap().ensure_available().is_ok());
+	}
+}
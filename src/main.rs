@@ -1,31 +1,96 @@
-use anyhow::Result;
-use clap::{App, Arg, SubCommand};
+use std::{path::{Path, PathBuf}, thread, time::Duration};
 
-// https://stackoverflow.com/questions/8652948/using-port-number-in-windows-host-file
+use anyhow::{Result, bail};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
-
-mod command;
-mod util;
-
-pub use util::*;
+use localhosting::{
+	BackendKind, Connection, Error, Format, Manager, Mock, Project, hosts_file_path, has_write_permissions, DEFAULT_DNS_LISTEN,
+	command::{cert::Encoding, probe::wait_for}
+};
 
 
 const ARG_NAME_ADDRESS: &str = "ADDRESS";
 const ARG_NAME_HOST: &str = "HOST";
 const ARG_NAME_ADDRESS_HOST: &str = "ADDRESS/HOST";
+const ARG_NAME_BACKEND: &str = "BACKEND";
+const ARG_NAME_HOSTS_FILE: &str = "HOSTS_FILE";
+const ARG_NAME_GROUP: &str = "GROUP";
+const ARG_NAME_LISTEN_PORT: &str = "LISTEN_PORT";
+const ARG_NAME_BACKUP: &str = "BACKUP";
+const ARG_NAME_FIX: &str = "FIX";
+const ARG_NAME_PROJECT_FILE: &str = "PROJECT_FILE";
+const ARG_NAME_ALLOCATION: &str = "ALLOCATION";
+const ARG_NAME_SUBNET: &str = "SUBNET";
+const ARG_NAME_FORMAT: &str = "FORMAT";
+const ARG_NAME_DRY_RUN: &str = "DRY_RUN";
+const ARG_NAME_LISTEN: &str = "LISTEN";
+const ARG_NAME_UPSTREAM: &str = "UPSTREAM";
+const ARG_NAME_EXPORT: &str = "EXPORT";
+const ARG_NAME_DER: &str = "DER";
+const ARG_NAME_TLS: &str = "TLS";
+const ARG_NAME_MOCK: &str = "MOCK";
+const ARG_NAME_PROBE: &str = "PROBE";
+const ARG_NAME_NO_SERVER: &str = "NO_SERVER";
 
 
 const COMMAND_NAME_ADD: &str = "add";
 const COMMAND_NAME_REMOVE: &str = "remove";
 const COMMAND_NAME_LIST: &str = "list";
 const COMMAND_NAME_TEST: &str = "test";
+const COMMAND_NAME_SERVE: &str = "serve";
+const COMMAND_NAME_RESTORE: &str = "restore";
+const COMMAND_NAME_DOCTOR: &str = "doctor";
+const COMMAND_NAME_UP: &str = "up";
+const COMMAND_NAME_DOWN: &str = "down";
+const COMMAND_NAME_DNS: &str = "dns";
+const COMMAND_NAME_CERT: &str = "cert";
+
+
+fn main() {
+	if let Err(e) = run() {
+		eprintln!("Error: {:#}", e);
+
+		let code = e.downcast_ref::<Error>()
+			.map(Error::exit_code)
+			.unwrap_or(1);
 
+		std::process::exit(code);
+	}
+}
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
 	let matches = App::new("Local Hoster")
 		.version("1.0")
 		.author("Tim F. (https://github.com/Its-its/localhosting)")
 		.about("Makes it easy for you to reverse-proxy your hosts on Windows.")
+		.arg(Arg::with_name(ARG_NAME_BACKEND)
+			.long("backend")
+			.help("Sets how bridges are forwarded. Defaults to netsh on Windows, otherwise nat")
+			.takes_value(true)
+			.possible_values(&BackendKind::NAMES)
+			.global(true))
+		.arg(Arg::with_name(ARG_NAME_HOSTS_FILE)
+			.long("hosts-file")
+			.help("Sets the hosts file to use. Can also be set with the LOCALHOSTING_HOSTS_FILE env var")
+			.takes_value(true)
+			.global(true))
+		.arg(Arg::with_name(ARG_NAME_FORMAT)
+			.long("format")
+			.help("Sets how results are printed. Defaults to table")
+			.takes_value(true)
+			.possible_values(&["table", "json", "yaml"])
+			.global(true))
+		.arg(Arg::with_name(ARG_NAME_ALLOCATION)
+			.long("allocation")
+			.help("Sets how listening addresses of new bridges are picked and remembers it. Defaults to hash")
+			.takes_value(true)
+			.possible_values(&["hash", "sequential", "random"])
+			.global(true))
+		.arg(Arg::with_name(ARG_NAME_SUBNET)
+			.long("subnet")
+			.help("Sets the loopback subnet listening addresses are picked from and remembers it. Defaults to 127.0.0.0/8")
+			.takes_value(true)
+			.global(true))
 		// Add
 		.subcommand(
 			SubCommand::with_name(COMMAND_NAME_ADD)
@@ -36,12 +101,21 @@ fn main() -> Result<()> {
 			.arg(Arg::with_name(ARG_NAME_HOST)
 				.help("Sets the listening host (example.com)")
 				.required(true))
+			.arg(Arg::with_name(ARG_NAME_GROUP)
+				.long("group")
+				.help("Adds the host onto the same hosts file line as the others using the address"))
+			.arg(Arg::with_name(ARG_NAME_LISTEN_PORT)
+				.long("listen-port")
+				.help("Sets the port the host listens on (443). Defaults to 80")
+				.takes_value(true))
+			.arg(dry_run_arg())
 		)
 		// Remove
 		.subcommand(
 			SubCommand::with_name(COMMAND_NAME_REMOVE)
 			.about("Remove listener based on Address OR Host")
 			.arg(Arg::with_name(ARG_NAME_ADDRESS_HOST).required(true))
+			.arg(dry_run_arg())
 		)
 		// List
 		.subcommand(SubCommand::with_name(COMMAND_NAME_LIST).about("List host listeners"))
@@ -50,127 +124,322 @@ fn main() -> Result<()> {
 			SubCommand::with_name(COMMAND_NAME_TEST)
 			.about("Test listener(s) based on Address OR Host")
 			.arg(Arg::with_name(ARG_NAME_ADDRESS_HOST).required(true))
+			.arg(tls_arg())
+			.arg(Arg::with_name(ARG_NAME_MOCK)
+				.long("mock")
+				.help("Sets how [HOST=]MODE is answered: text, echo, static:<dir> or routes:<file>. Without HOST every host")
+				.takes_value(true)
+				.multiple(true)
+				.number_of_values(1))
+			.arg(Arg::with_name(ARG_NAME_PROBE)
+				.long("probe")
+				.help("Checks each hop (resolve, listening address, target) through the hosts file and bridge, then exits"))
+			.arg(Arg::with_name(ARG_NAME_NO_SERVER)
+				.long("no-server")
+				.requires(ARG_NAME_PROBE)
+				.help("Probes whatever is already running on the target instead of starting the webserver"))
+		)
+		// Serve
+		.subcommand(
+			SubCommand::with_name(COMMAND_NAME_SERVE)
+			.about("Run the built-in reverse proxy for bridges added with the userspace backend")
+			.arg(tls_arg())
+		)
+		// Restore
+		.subcommand(
+			SubCommand::with_name(COMMAND_NAME_RESTORE)
+			.about("List hosts file backups or restore one")
+			.arg(Arg::with_name(ARG_NAME_BACKUP).help("Backup file name or number shown in the list"))
+		)
+		// Doctor
+		.subcommand(
+			SubCommand::with_name(COMMAND_NAME_DOCTOR)
+			.alias("sync")
+			.about("Find bridges and hosts which no longer match up")
+			.arg(Arg::with_name(ARG_NAME_FIX)
+				.long("fix")
+				.help("Removes orphaned bridges and hosts and recreates missing ones"))
+			.arg(dry_run_arg())
+		)
+		// Up
+		.subcommand(
+			SubCommand::with_name(COMMAND_NAME_UP)
+			.about("Add every host listed in the project file")
+			.arg(project_file_arg())
+			.arg(dry_run_arg())
+		)
+		// Down
+		.subcommand(
+			SubCommand::with_name(COMMAND_NAME_DOWN)
+			.about("Remove every host the project file added")
+			.arg(project_file_arg())
+		)
+		// DNS
+		.subcommand(
+			SubCommand::with_name(COMMAND_NAME_DNS)
+			.about("Run a DNS responder answering the managed hosts, including wildcards (*.example.com)")
+			.arg(Arg::with_name(ARG_NAME_LISTEN)
+				.long("listen")
+				.help("Sets the loopback address to listen on. Defaults to 127.0.0.1:53")
+				.takes_value(true))
+			.arg(Arg::with_name(ARG_NAME_UPSTREAM)
+				.long("upstream")
+				.help("Forwards other names to this resolver (1.1.1.1:53) instead of refusing them")
+				.takes_value(true))
+		)
+		// Cert
+		.subcommand(
+			SubCommand::with_name(COMMAND_NAME_CERT)
+			.about("Issue or renew a certificate for every host, signed by a local root certificate")
+			.arg(Arg::with_name(ARG_NAME_EXPORT)
+				.long("export")
+				.help("Exports the root certificate to the file so it can be imported into trust stores")
+				.takes_value(true))
+			.arg(Arg::with_name(ARG_NAME_DER)
+				.long("der")
+				.requires(ARG_NAME_EXPORT)
+				.help("Exports the root certificate as DER instead of PEM"))
 		)
 		.get_matches();
 
 
-	let mut netsh = NetSH::create(ProxyBridge::V4ToV4)?;
-	let mut hosts = HostFile::read()?;
+	let hosts_path = hosts_file_path(global_value(&matches, ARG_NAME_HOSTS_FILE));
+
+	// The proxy should only ever forward bridges which aren't already handled by the OS.
+	let backend = match global_value(&matches, ARG_NAME_BACKEND) {
+		Some(v) => v.parse()?,
+		None if matches.subcommand_name() == Some(COMMAND_NAME_SERVE) => BackendKind::Userspace,
+		None => BackendKind::platform()
+	};
+
+	let mut manager = Manager::open(backend.create()?, hosts_path.clone())?;
+
+	// Read-only commands and dry runs never touch the system, not even to undo an interrupted run.
+	let is_changing = match matches.subcommand() {
+		(COMMAND_NAME_ADD, Some(v)) | (COMMAND_NAME_REMOVE, Some(v)) | (COMMAND_NAME_UP, Some(v)) => !v.is_present(ARG_NAME_DRY_RUN),
+		(COMMAND_NAME_DOCTOR, Some(v)) => v.is_present(ARG_NAME_FIX) && !v.is_present(ARG_NAME_DRY_RUN),
+		(COMMAND_NAME_RESTORE, Some(v)) => v.is_present(ARG_NAME_BACKUP),
+		(COMMAND_NAME_DOWN, _) => true,
+		_ => false
+	};
+
+	if is_changing {
+		ensure_writable(&hosts_path)?;
+
+		if manager.recover()? {
+			eprintln!("Undid changes from an interrupted run.");
+		}
+	} else if manager.is_interrupted() {
+		eprintln!("A previous run was interrupted. Its' changes are undone by the next command changing something.");
+	}
+
+	let allocator = manager.state().allocator;
+
+	if let Some(value) = global_value(&matches, ARG_NAME_ALLOCATION) {
+		manager.state_mut().allocator.strategy = value.parse()?;
+	}
+
+	if let Some(value) = global_value(&matches, ARG_NAME_SUBNET) {
+		manager.state_mut().allocator.subnet = value.parse()?;
+	}
+
+	// Replaces the stored settings. Later runs keep using them without the flags.
+	if manager.state().allocator != allocator && is_changing {
+		manager.state().save()?;
+	}
+
+	let format: Format = global_value(&matches, ARG_NAME_FORMAT).unwrap_or("table").parse()?;
 
 	match matches.subcommand() {
 		(COMMAND_NAME_ADD, Some(matches)) => {
-			if has_write_permissions() {
-				let address = matches.value_of(ARG_NAME_ADDRESS).unwrap();
-				let host = matches.value_of(ARG_NAME_HOST).unwrap();
+			let address = matches.value_of(ARG_NAME_ADDRESS).unwrap();
+			let host = matches.value_of(ARG_NAME_HOST).unwrap();
+
+			let listen_port = matches.value_of(ARG_NAME_LISTEN_PORT)
+				.map(|v| v.parse::<u16>().map_err(|e| Error::parse(v, e)))
+				.transpose()?;
+
+			manager.hosts_mut().group_hosts = matches.is_present(ARG_NAME_GROUP);
 
-				command::add::process(address, host, &mut netsh, &mut hosts)?;
-			} else {
-				println!("Please run as Administrator.");
+			if matches.is_present(ARG_NAME_DRY_RUN) {
+				let plan = manager.plan_add(address, host, listen_port)?;
+				println!("{}", format.render(&plan)?);
+				return Ok(());
 			}
+
+			let added = manager.add(address, host, listen_port)?;
+			println!("{}", format.render(&added)?);
 		}
 
 		(COMMAND_NAME_REMOVE, Some(matches)) => {
-			if has_write_permissions() {
-				let addr_or_host = matches.value_of(ARG_NAME_ADDRESS_HOST).unwrap();
-				command::remove::process(addr_or_host, &mut netsh, &mut hosts)?;
-			} else {
-				println!("Please run as Administrator.");
+			let addr_or_host = matches.value_of(ARG_NAME_ADDRESS_HOST).unwrap();
+
+			if matches.is_present(ARG_NAME_DRY_RUN) {
+				let plan = manager.plan_remove(addr_or_host)?;
+				println!("{}", format.render(&plan)?);
+				return Ok(());
 			}
+
+			let removed = manager.remove(addr_or_host)?;
+			println!("{}", format.render(&removed)?);
 		}
 
 		(COMMAND_NAME_LIST, _) => {
-			command::list::process(&netsh, &hosts)?;
+			let listing = manager.list()?;
+			println!("{}", format.render(&listing)?);
 		}
 
 		(COMMAND_NAME_TEST, Some(matches)) => {
 			let addr_or_host = matches.value_of(ARG_NAME_ADDRESS_HOST).unwrap();
-			command::test::process(addr_or_host, &netsh, &hosts)?;
-		}
+			let mut testing = if matches.is_present(ARG_NAME_TLS) { manager.test_tls(addr_or_host)? } else { manager.test(addr_or_host)? };
 
-		_ => ()
-	}
+			for value in matches.values_of(ARG_NAME_MOCK).into_iter().flatten() {
+				// The mode may contain "=" but the host never contains ":".
+				let (host, mode) = match value.split_once('=') {
+					Some((host, mode)) if !host.contains(':') => (Some(host), mode),
+					_ => (None, value)
+				};
 
-	Ok(())
-}
+				testing = testing.with_mock(host, Mock::parse(mode)?)?;
+			}
 
+			if matches.is_present(ARG_NAME_PROBE) {
+				if !matches.is_present(ARG_NAME_NO_SERVER) {
+					// Otherwise whatever is already listening would be probed.
+					testing.ensure_available()?;
 
-#[cfg(test)]
-mod tests {
-	use crate::{NetSH, HostFile, command, Connection};
+					let listening = testing.listening.iter().map(|v| v.connect_to).collect::<Vec<_>>();
 
-	const ADDRESS_HOST_COMBOS: [(&str, &str); 6] = [
-		("127.0.0.1:8080", "one.test"),
-		("127.0.0.1:8080", "a.one.test"),
-		("127.0.0.1:8080", "b.one.test"),
+					thread::spawn(move || testing.run());
 
-		("127.0.0.1:8081", "two.test"),
-		("127.0.0.1:8081", "a.two.test"),
+					for connect_to in listening {
+						wait_for(connect_to, Duration::from_secs(5));
+					}
+				}
 
-		("127.0.0.1:8082", "three.test")
-	];
+				let probed = manager.probe(addr_or_host)?;
+				println!("{}", format.render(&probed)?);
 
-	#[test]
-	fn add_successes() {
-		let (mut netsh, mut hosts) = (NetSH::default(), HostFile::default());
+				if !probed.passed() {
+					bail!("Probe failed");
+				}
 
-		for (addr, host) in ADDRESS_HOST_COMBOS {
-			command::add::process(addr, host, &mut netsh, &mut hosts).unwrap();
+				return Ok(());
+			}
+
+			println!("{}", format.render(&testing)?);
+
+			testing.run()?;
 		}
 
-		// Attempt to add a duplicate (Should not add anything)
-		command::add::process(ADDRESS_HOST_COMBOS.last().unwrap().0, ADDRESS_HOST_COMBOS.last().unwrap().1, &mut netsh, &mut hosts).unwrap();
+		(COMMAND_NAME_SERVE, Some(matches)) => {
+			let serving = if matches.is_present(ARG_NAME_TLS) { manager.serve_tls()? } else { manager.serve()? };
+			println!("{}", format.render(&serving)?);
 
-		// Compare bridge listeners against ones which were attempted to add. (each iter for counts will be [3, 2, 1])
-		for bridge in &netsh.bridges {
-			let combo_count = ADDRESS_HOST_COMBOS.iter().filter(|(v, _)| bridge.connect_to == v.parse::<Connection>().unwrap()).count();
+			serving.run()?;
+		}
 
-			let registered_count = hosts.count_addresses(bridge.listen_to.address);
+		(COMMAND_NAME_RESTORE, Some(matches)) => {
+			let backup = matches.value_of(ARG_NAME_BACKUP);
 
-			assert_eq!(combo_count, registered_count, "Unique Bridge Listeners");
+			let restored = manager.restore(backup)?;
+			println!("{}", format.render(&restored)?);
 		}
 
-		assert_eq!(3, netsh.bridges.len(), "Bridges Count (Unique addresses)");
-		assert_eq!(6, hosts.items.len(), "Hosts Length (Unique Connections in Hosts File)");
-	}
+		(COMMAND_NAME_DOCTOR, Some(matches)) => {
+			let fix = matches.is_present(ARG_NAME_FIX);
+
+			if matches.is_present(ARG_NAME_DRY_RUN) {
+				let plan = manager.plan_doctor(fix)?;
+				println!("{}", format.render(&plan)?);
+				return Ok(());
+			}
+
+			let diagnosis = manager.doctor(fix)?;
+			println!("{}", format.render(&diagnosis)?);
+		}
 
-	#[test]
-	fn remove_successes() {
-		let (mut netsh, mut hosts) = (NetSH::default(), HostFile::default());
+		(COMMAND_NAME_UP, Some(matches)) => {
+			let project = Project::load(&project_file_path(matches)?)?;
 
-		for (addr, host) in ADDRESS_HOST_COMBOS {
-			command::add::process(addr, host, &mut netsh, &mut hosts).unwrap();
+			if matches.is_present(ARG_NAME_DRY_RUN) {
+				let plan = manager.plan_up(&project)?;
+				println!("{}", format.render(&plan)?);
+				return Ok(());
+			}
+
+			let upped = manager.up(&project)?;
+			println!("{}", format.render(&upped)?);
 		}
 
-		// Start off with initial state. Should be correct.
-		assert_eq!(3, netsh.bridges.len(), "[Initial] Bridges Count (Unique addresses)");
-		assert_eq!(6, hosts.items.len(), "[Initial] Hosts Length (Unique Connections in Hosts File)");
+		(COMMAND_NAME_DOWN, Some(matches)) => {
+			let project = Project::load(&project_file_path(matches)?)?;
+			let downed = manager.down(&project.id)?;
+			println!("{}", format.render(&downed)?);
+		}
 
-		// Remove single host only
-		command::remove::process("a.one.test", &mut netsh, &mut hosts).unwrap();
+		(COMMAND_NAME_DNS, Some(matches)) => {
+			let listen: Connection = matches.value_of(ARG_NAME_LISTEN).unwrap_or(DEFAULT_DNS_LISTEN).parse()?;
+			let upstream = matches.value_of(ARG_NAME_UPSTREAM).map(str::parse::<Connection>).transpose()?;
 
-		assert_eq!(3, netsh.bridges.len(), "[1 Host Removal] Bridges Count (Unique addresses)");
-		assert_eq!(5, hosts.items.len(), "[1 Host Removal] Hosts Length (Unique Connections in Hosts File)");
+			let responding = manager.dns(listen, upstream)?;
+			println!("{}", format.render(&responding)?);
 
-		// Remove single host only
-		command::remove::process("two.test", &mut netsh, &mut hosts).unwrap();
+			responding.run()?;
+		}
 
-		assert_eq!(3, netsh.bridges.len(), "[2 Hosts Removal] Bridges Count (Unique addresses)");
-		assert_eq!(4, hosts.items.len(), "[2 Hosts Removal] Hosts Length (Unique Connections in Hosts File)");
+		(COMMAND_NAME_CERT, Some(matches)) => {
+			let encoding = if matches.is_present(ARG_NAME_DER) { Encoding::Der } else { Encoding::Pem };
+			let export = matches.value_of(ARG_NAME_EXPORT).map(|v| (Path::new(v), encoding));
 
-		// Remove multiple hosts and bridge
-		command::remove::process("127.0.0.1:8080", &mut netsh, &mut hosts).unwrap();
+			let certified = manager.cert(export)?;
+			println!("{}", format.render(&certified)?);
+		}
 
-		assert_eq!(2, netsh.bridges.len(), "[2 Hosts + Bridge Removal] Bridges Count (Unique addresses)");
-		assert_eq!(2, hosts.items.len(), "[2 Hosts + Bridge Removal] Hosts Length (Unique Connections in Hosts File)");
+		_ => ()
 	}
 
-	#[test]
-	fn add_failures() {
-		//
+	Ok(())
+}
+
+fn ensure_writable(path: &Path) -> Result<()> {
+	if has_write_permissions(path) {
+		Ok(())
+	} else {
+		Err(Error::PermissionDenied(path.to_path_buf()).into())
 	}
+}
 
-	#[test]
-	fn remove_failures() {
-		//
+fn project_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+	Arg::with_name(ARG_NAME_PROJECT_FILE)
+		.long("file")
+		.help("Sets the project file. Defaults to the closest localhosting.toml")
+		.takes_value(true)
+}
+
+fn dry_run_arg<'a, 'b>() -> Arg<'a, 'b> {
+	Arg::with_name(ARG_NAME_DRY_RUN)
+		.long("dry-run")
+		.help("Shows the hosts file changes and backend commands without running them")
+}
+
+fn tls_arg<'a, 'b>() -> Arg<'a, 'b> {
+	Arg::with_name(ARG_NAME_TLS)
+		.long("tls")
+		.help("Serves HTTPS using the hosts' certificates, issuing them first (see cert)")
+}
+
+fn project_file_path(matches: &ArgMatches) -> Result<PathBuf> {
+	match matches.value_of(ARG_NAME_PROJECT_FILE) {
+		Some(v) => Ok(PathBuf::from(v)),
+		None => Project::find(&std::env::current_dir()?)
 	}
-}
\ No newline at end of file
+}
+
+/// Global args are only stored in the matches of the subcommand they were specified after.
+fn global_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+	matches.subcommand()
+		.1
+		.and_then(|v| v.value_of(name))
+		.or_else(|| matches.value_of(name))
+}
@@ -0,0 +1,297 @@
+use std::{path::{Path, PathBuf}, str::FromStr};
+
+use anyhow::{Result, bail};
+use time::OffsetDateTime;
+
+use crate::{
+	Backups, CertResolver, CertStore, Change, Connection, DryRun, Error, FileStore, ForwardingBackend, HostFile, Journal, MemoryBackend, MemoryStore, NatRules, NetSH, Plan,
+	Project, State, UserspaceProxy,
+	command::{
+		add::Added,
+		cert::{Certified, Encoding},
+		dns::Responding,
+		doctor::Diagnosis,
+		down::Downed,
+		list::Listing,
+		probe::Probed,
+		remove::Removed,
+		restore::Restored,
+		serve::Serving,
+		test::Testing,
+		up::Upped,
+		self
+	},
+	diff_lines
+};
+
+
+/// Which forwarding backend to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+	NetSH,
+	Nat,
+	Userspace
+}
+
+impl BackendKind {
+	pub const NAMES: [&'static str; 3] = ["netsh", "nat", "userspace"];
+
+	/// netsh on Windows, otherwise nat.
+	pub fn platform() -> Self {
+		if cfg!(windows) { Self::NetSH } else { Self::Nat }
+	}
+
+	pub fn create(self) -> Result<Box<dyn ForwardingBackend>> {
+		Ok(match self {
+			Self::NetSH => Box::new(NetSH::create()?),
+			Self::Nat => Box::new(NatRules::detect()?),
+			Self::Userspace => Box::new(UserspaceProxy::load(UserspaceProxy::default_path()?)?)
+		})
+	}
+}
+
+impl FromStr for BackendKind {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"netsh" => Ok(Self::NetSH),
+			"nat" => Ok(Self::Nat),
+			"userspace" => Ok(Self::Userspace),
+			_ => Err(Error::parse(s, "Expected netsh, nat or userspace"))
+		}
+	}
+}
+
+
+/// Everything needed to manage hosts. Each method returns what it did instead of printing.
+///
+/// ```no_run
+/// use localhosting::{BackendKind, Manager, hosts_file_path};
+///
+/// let mut manager = Manager::open(BackendKind::platform().create()?, hosts_file_path(None))?;
+///
+/// let added = manager.add("127.0.0.1:8080", "example.test", None)?;
+/// println!("{} is listening on {}", added.host, added.listen_to);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Manager {
+	backend: Box<dyn ForwardingBackend>,
+	hosts: HostFile,
+	state: State,
+	journal: Journal,
+	backups: Option<Backups>,
+	certs: Option<CertStore>
+}
+
+impl Manager {
+	pub fn new(backend: Box<dyn ForwardingBackend>, hosts: HostFile, state: State, journal: Journal) -> Self {
+		Self {
+			backend,
+			hosts,
+			state,
+			journal,
+			backups: None,
+			certs: None
+		}
+	}
+
+	/// Uses the certificate authority inside the directory.
+	pub fn with_cert_store(mut self, store: CertStore) -> Self {
+		self.certs = Some(store);
+		self
+	}
+
+	/// Keeps everything in memory. Nothing on the system is touched.
+	pub fn in_memory() -> Self {
+		Self::new(Box::new(MemoryBackend::default()), HostFile::default(), State::default(), Journal::default())
+	}
+
+	/// Uses the hosts file at the path and the state, journal, backups and certificates inside the users' directories.
+	///
+	/// Nothing is written. An interrupted transaction stays until `recover` is called.
+	pub fn open(backend: Box<dyn ForwardingBackend>, hosts_path: PathBuf) -> Result<Self> {
+		let backups = Backups::new(Backups::default_dir()?);
+		let hosts = HostFile::load(Box::new(FileStore::new(hosts_path).with_backups(backups.clone())))?;
+
+		let journal = Journal::load(Journal::default_path()?)?;
+		let state = State::load_or_discover(State::default_path()?, backend.as_ref(), &hosts)?;
+
+		Ok(Self {
+			backend,
+			hosts,
+			state,
+			journal,
+			backups: Some(backups),
+			certs: Some(CertStore::new(CertStore::default_dir()?))
+		})
+	}
+
+	/// Undoes an interrupted transaction. Returns whether there was one.
+	///
+	/// Changes the hosts file and bridges, so it's only called before changing them anyway.
+	pub fn recover(&mut self) -> Result<bool> {
+		if !self.journal.is_interrupted() {
+			return Ok(false);
+		}
+
+		self.journal.recover(self.backend.as_mut(), &mut self.hosts)?;
+
+		Ok(true)
+	}
+
+	/// Whether a previous transaction still has to be undone. Nothing can be changed until it is.
+	pub fn is_interrupted(&self) -> bool {
+		self.journal.is_interrupted()
+	}
+
+	pub fn backend(&self) -> &dyn ForwardingBackend {
+		self.backend.as_ref()
+	}
+
+	pub fn hosts(&self) -> &HostFile {
+		&self.hosts
+	}
+
+	pub fn hosts_mut(&mut self) -> &mut HostFile {
+		&mut self.hosts
+	}
+
+	pub fn state(&self) -> &State {
+		&self.state
+	}
+
+	pub fn state_mut(&mut self) -> &mut State {
+		&mut self.state
+	}
+
+
+	pub fn add(&mut self, address: &str, host: &str, listen_port: Option<u16>) -> Result<Added> {
+		command::add::process(address, host, listen_port, self.backend.as_mut(), &mut self.hosts, &mut self.state, &mut self.journal)
+	}
+
+	pub fn remove(&mut self, addr_or_host: &str) -> Result<Removed> {
+		command::remove::process(addr_or_host, self.backend.as_mut(), &mut self.hosts, &mut self.state, &mut self.journal)
+	}
+
+	pub fn list(&self) -> Result<Listing> {
+		command::list::process(self.backend.as_ref(), &self.state)
+	}
+
+	/// Finds what to test. Call `run` on the result to start the webserver.
+	pub fn test(&self, addr_or_host: &str) -> Result<Testing> {
+		command::test::process(addr_or_host, &self.state)
+	}
+
+	/// Like `test` but serves HTTPS. Certificates are issued or renewed first.
+	pub fn test_tls(&self, addr_or_host: &str) -> Result<Testing> {
+		self.test(addr_or_host)?.with_tls(self.cert_resolver()?)
+	}
+
+	/// Checks every hop from resolving the host to reaching its' target. Nothing is started.
+	pub fn probe(&self, addr_or_host: &str) -> Result<Probed> {
+		command::probe::process(addr_or_host, &self.state, &command::probe::system_resolve)
+	}
+
+	/// Finds what to proxy. Call `run` on the result to start the proxy.
+	pub fn serve(&self) -> Result<Serving> {
+		command::serve::process(self.backend.as_ref(), &self.state)
+	}
+
+	/// Like `serve` but terminates HTTPS. Certificates are issued or renewed first.
+	pub fn serve_tls(&self) -> Result<Serving> {
+		self.serve()?.with_tls(self.cert_resolver()?)
+	}
+
+	/// Finds what to answer. Call `run` on the result to start the DNS responder.
+	pub fn dns(&self, listen: Connection, upstream: Option<Connection>) -> Result<Responding> {
+		command::dns::process(listen, upstream, &self.state)
+	}
+
+	pub fn doctor(&mut self, fix: bool) -> Result<Diagnosis> {
+		command::doctor::process(fix, self.backend.as_mut(), &mut self.hosts, &mut self.state, &mut self.journal)
+	}
+
+	pub fn up(&mut self, project: &Project) -> Result<Upped> {
+		command::up::process(project, self.backend.as_mut(), &mut self.hosts, &mut self.state, &mut self.journal)
+	}
+
+	pub fn down(&mut self, project_id: &str) -> Result<Downed> {
+		command::down::process(project_id, self.backend.as_mut(), &mut self.hosts, &mut self.state, &mut self.journal)
+	}
+
+	/// Lists the backups or restores one.
+	pub fn restore(&mut self, backup: Option<&str>) -> Result<Restored> {
+		let backups = self.backups.as_ref().ok_or_else(|| Error::NotFound("Backups".to_string()))?;
+
+		command::restore::process(backup, backups, &mut self.hosts)
+	}
+
+	/// Issues or renews the certificate of every managed host. Optionally exports the root.
+	pub fn cert(&self, export: Option<(&Path, Encoding)>) -> Result<Certified> {
+		let store = self.certs.as_ref().ok_or_else(|| Error::NotFound("Certificate store".to_string()))?;
+
+		command::cert::process(&self.hosts, &self.state, store, export, OffsetDateTime::now_utc())
+	}
+
+	fn cert_resolver(&self) -> Result<CertResolver> {
+		self.cert(None)?;
+
+		let store = self.certs.as_ref().ok_or_else(|| Error::NotFound("Certificate store".to_string()))?;
+
+		CertResolver::load(store, &self.hosts, &self.state)
+	}
+
+
+	/// What `add` would change.
+	pub fn plan_add(&self, address: &str, host: &str, listen_port: Option<u16>) -> Result<Plan> {
+		self.plan(|backend, hosts, state, journal| {
+			Ok(command::add::process(address, host, listen_port, backend, hosts, state, journal)?.changes)
+		})
+	}
+
+	/// What `remove` would change.
+	pub fn plan_remove(&self, addr_or_host: &str) -> Result<Plan> {
+		self.plan(|backend, hosts, state, journal| {
+			Ok(command::remove::process(addr_or_host, backend, hosts, state, journal)?.changes)
+		})
+	}
+
+	/// What `doctor` would change. Nothing unless `fix` is set.
+	pub fn plan_doctor(&self, fix: bool) -> Result<Plan> {
+		self.plan(|backend, hosts, state, journal| {
+			Ok(command::doctor::process(fix, backend, hosts, state, journal)?.changes)
+		})
+	}
+
+	/// What `up` would change.
+	pub fn plan_up(&self, project: &Project) -> Result<Plan> {
+		self.plan(|backend, hosts, state, journal| {
+			Ok(command::up::process(project, backend, hosts, state, journal)?.changes)
+		})
+	}
+
+	/// Runs the command against copies of the hosts file and state and a backend which only records commands.
+	fn plan<F>(&self, f: F) -> Result<Plan>
+	where
+		F: FnOnce(&mut dyn ForwardingBackend, &mut HostFile, &mut State, &mut Journal) -> Result<Vec<Change>>
+	{
+		if self.is_interrupted() {
+			bail!("A previous run was interrupted. Its' changes have to be recovered first.");
+		}
+
+		let before = self.hosts.document().to_string();
+
+		let mut backend = DryRun::new(self.backend.as_ref());
+		let mut hosts = HostFile::load(Box::new(MemoryStore { contents: before.clone() }))?;
+		hosts.group_hosts = self.hosts.group_hosts;
+
+		let changes = f(&mut backend, &mut hosts, &mut self.state.detached(), &mut Journal::default())?;
+
+		Ok(Plan {
+			changes,
+			hosts_diff: diff_lines(&before, &hosts.document().to_string()),
+			commands: backend.commands
+		})
+	}
+}
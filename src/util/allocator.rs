@@ -0,0 +1,182 @@
+use std::{fmt, net::{IpAddr, Ipv4Addr}, str::FromStr};
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Connection, Error};
+
+
+/// Port bridges listen on unless another one is given.
+pub const DEFAULT_LISTEN_PORT: u16 = 80;
+
+
+/// How the listening address of a new bridge is picked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+	/// Derived from the host name so it's the same on every machine.
+	#[default]
+	Hash,
+	/// First unused address of the subnet.
+	Sequential,
+	Random
+}
+
+impl FromStr for Strategy {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"hash" => Ok(Self::Hash),
+			"sequential" => Ok(Self::Sequential),
+			"random" => Ok(Self::Random),
+			_ => Err(Error::parse(s, "Expected hash, sequential or random"))
+		}
+	}
+}
+
+
+/// Loopback subnet addresses are allocated from. (127.0.0.0/8)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+	pub network: Ipv4Addr,
+	pub prefix: u8
+}
+
+impl Subnet {
+	/// Amount of addresses inside the subnet.
+	pub fn size(&self) -> u64 {
+		1 << (32 - self.prefix as u32)
+	}
+
+	/// Address at `offset` from the start of the subnet.
+	pub fn nth(&self, offset: u64) -> Ipv4Addr {
+		Ipv4Addr::from(u32::from(self.network).wrapping_add(offset as u32))
+	}
+
+	pub fn contains(&self, address: IpAddr) -> bool {
+		match address {
+			IpAddr::V4(v) => u32::from(v) & self.mask() == u32::from(self.network),
+			IpAddr::V6(_) => false
+		}
+	}
+
+	fn mask(&self) -> u32 {
+		u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+	}
+}
+
+impl Default for Subnet {
+	fn default() -> Self {
+		Self {
+			network: Ipv4Addr::new(127, 0, 0, 0),
+			prefix: 8
+		}
+	}
+}
+
+impl FromStr for Subnet {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (network, prefix) = s.split_once('/').ok_or_else(|| Error::parse(s, "Expected <address>/<prefix>"))?;
+
+		let network = network.parse::<Ipv4Addr>().map_err(|e| Error::parse(s, e))?;
+		let prefix = prefix.parse::<u8>().map_err(|e| Error::parse(s, e))?;
+
+		if !network.is_loopback() {
+			return Err(Error::parse(s, "Expected a loopback (127.*.*.*) subnet"));
+		}
+
+		if !(8..=30).contains(&prefix) {
+			return Err(Error::parse(s, "Expected a prefix between 8 and 30"));
+		}
+
+		let mut subnet = Self { network, prefix };
+		subnet.network = Ipv4Addr::from(u32::from(network) & subnet.mask());
+
+		// Every address of it would be skipped by `is_usable`.
+		let [_, b, c, _] = subnet.network.octets();
+
+		if prefix >= 24 && b == 0 && c == 0 {
+			return Err(Error::parse(s, "127.0.0.* is left to other programs. Expected a subnet outside of it"));
+		}
+
+		Ok(subnet)
+	}
+}
+
+impl fmt::Display for Subnet {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/{}", self.network, self.prefix)
+	}
+}
+
+/// Stored as "127.0.0.0/8"
+impl Serialize for Subnet {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let value = String::deserialize(deserializer)?;
+		value.parse().map_err(serde::de::Error::custom)
+	}
+}
+
+
+/// Picks unused listening addresses inside a subnet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Allocator {
+	pub strategy: Strategy,
+	pub subnet: Subnet
+}
+
+impl Allocator {
+	/// Finds an unused listening address for the host.
+	///
+	/// `previous` is reused if it's still free, otherwise the subnet is walked starting at the strategies' offset.
+	pub fn allocate(&self, host: &str, port: u16, previous: Option<IpAddr>, is_used: impl Fn(IpAddr) -> bool) -> Result<Connection> {
+		if let Some(address) = previous {
+			if self.subnet.contains(address) && is_usable(address) && !is_used(address) {
+				return Ok((address, port).into());
+			}
+		}
+
+		let size = self.subnet.size();
+
+		let start = match self.strategy {
+			Strategy::Hash => fnv1a(host.to_ascii_lowercase().as_bytes()) % size,
+			Strategy::Sequential => 0,
+			Strategy::Random => rand::random::<u64>() % size
+		};
+
+		(0..size)
+			.map(|i| IpAddr::V4(self.subnet.nth((start + i) % size)))
+			.find(|v| is_usable(*v) && !is_used(*v))
+			.map(|v| (v, port).into())
+			.ok_or_else(|| Error::Exhausted(self.subnet.to_string()).into())
+	}
+}
+
+
+/// Since people normally use 127.0.0.* I don't want to infringe on it.
+/// Network and broadcast looking addresses are skipped as well.
+fn is_usable(address: IpAddr) -> bool {
+	match address {
+		IpAddr::V4(v) => {
+			let [_, b, c, d] = v.octets();
+			!(b == 0 && c == 0) && d != 0 && d != 255
+		}
+
+		IpAddr::V6(_) => false
+	}
+}
+
+/// Stable across platforms and versions, unlike the std hasher.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
@@ -0,0 +1,184 @@
+use std::{
+	fs::{self, OpenOptions},
+	io::{ErrorKind, Write},
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH}
+};
+
+use anyhow::{Result, Context};
+
+use crate::Error;
+
+
+const BACKUP_PREFIX: &str = "hosts-";
+const BACKUP_EXTENSION: &str = ".bak";
+
+/// Amount of backups kept by default.
+pub const DEFAULT_BACKUP_COUNT: usize = 10;
+
+
+/// Rotating set of timestamped hosts file backups.
+#[derive(Debug, Clone)]
+pub struct Backups {
+	pub dir: PathBuf,
+	/// Oldest backups are removed once there's more than this.
+	pub keep: usize
+}
+
+impl Backups {
+	pub fn new(dir: PathBuf) -> Self {
+		Self {
+			dir,
+			keep: DEFAULT_BACKUP_COUNT
+		}
+	}
+
+	/// Default location of the backups inside the users' local data directory.
+	pub fn default_dir() -> Result<PathBuf> {
+		let dir = dirs::data_local_dir().context("Unable to find local data directory")?;
+
+		Ok(dir.join("localhosting").join("backups"))
+	}
+
+	/// Stores the contents as the newest backup, then removes the oldest ones past `keep`.
+	pub fn create(&self, contents: &str) -> Result<PathBuf> {
+		fs::create_dir_all(&self.dir)?;
+
+		let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+
+		// Multiple writes can happen within the same millisecond.
+		let path = (0..)
+			.map(|i| self.dir.join(format!("{}{}-{:03}{}", BACKUP_PREFIX, millis, i, BACKUP_EXTENSION)))
+			.find(|v| !v.exists())
+			.unwrap();
+
+		write_atomic(&path, contents)?;
+
+		for old in self.list()?.into_iter().skip(self.keep) {
+			fs::remove_file(old)?;
+		}
+
+		Ok(path)
+	}
+
+	/// Every backup, newest first.
+	pub fn list(&self) -> Result<Vec<PathBuf>> {
+		if !self.dir.exists() {
+			return Ok(Vec::new());
+		}
+
+		let mut found = fs::read_dir(&self.dir)?
+			.filter_map(|v| v.ok())
+			.map(|v| v.path())
+			.filter(|v| {
+				v.file_name()
+					.and_then(|v| v.to_str())
+					.map(|v| v.starts_with(BACKUP_PREFIX) && v.ends_with(BACKUP_EXTENSION))
+					.unwrap_or_default()
+			})
+			.collect::<Vec<_>>();
+
+		// Timestamps are the same length so they sort by name.
+		found.sort();
+		found.reverse();
+
+		Ok(found)
+	}
+
+	/// Finds a backup by its' file name or position in `list` (0 being the newest).
+	pub fn find(&self, value: &str) -> Result<PathBuf> {
+		let list = self.list()?;
+
+		if let Ok(index) = value.parse::<usize>() {
+			if let Some(path) = list.get(index) {
+				return Ok(path.clone());
+			}
+		}
+
+		match list.into_iter().find(|v| v.file_name().and_then(|v| v.to_str()) == Some(value)) {
+			Some(v) => Ok(v),
+			None => Err(Error::NotFound(value.to_string()).into())
+		}
+	}
+}
+
+
+/// Writes to a temporary sibling file first and then renames it over the original.
+///
+/// The file is either fully written or left untouched. Symlinks are followed, so the file they point to is replaced.
+/// Files which can't be replaced (bind mounts like Docker's /etc/hosts) are written to directly instead.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+	write_replacing(path, contents.as_ref(), None)
+}
+
+/// Same as `write_atomic`, but only the user can ever read the file. Used for private keys.
+pub fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+	write_replacing(path, contents.as_ref(), Some(0o600))
+}
+
+/// `mode` is set when the temporary file is created, so the contents are never readable with looser permissions.
+fn write_replacing(path: &Path, contents: &[u8], mode: Option<u32>) -> Result<()> {
+	let path = &resolve_symlinks(path);
+	let file_name = path.file_name().context("Expected a file path")?.to_string_lossy();
+	let temp_path = path.with_file_name(format!(".{}.localhosting.tmp", file_name));
+
+	// A leftover of an interrupted run would keep its' permissions.
+	fs::remove_file(&temp_path).ok();
+
+	{
+		let mut options = OpenOptions::new();
+		options.write(true).create_new(true);
+
+		#[cfg(unix)]
+		if let Some(mode) = mode {
+			use std::os::unix::fs::OpenOptionsExt;
+			options.mode(mode);
+		}
+
+		let mut file = options.open(&temp_path)?;
+		file.write_all(contents)?;
+		file.sync_all()?;
+	}
+
+	// Keep the original permissions.
+	if let (None, Ok(meta)) = (mode, fs::metadata(path)) {
+		fs::set_permissions(&temp_path, meta.permissions())?;
+	}
+
+	if let Err(e) = fs::rename(&temp_path, path) {
+		fs::remove_file(&temp_path).ok();
+
+		// The contents are complete at this point, only replacing the file failed.
+		if matches!(e.kind(), ErrorKind::ResourceBusy | ErrorKind::CrossesDevices) {
+			return write_in_place(path, contents);
+		}
+
+		return Err(e.into());
+	}
+
+	Ok(())
+}
+
+/// Target of the symlink, or the path itself if it isn't one. Renaming over a symlink would replace the link.
+fn resolve_symlinks(path: &Path) -> PathBuf {
+	if let Ok(resolved) = fs::canonicalize(path) {
+		return resolved;
+	}
+
+	// Dangling links can't be canonicalized, the file they point to is created instead.
+	match fs::read_link(path) {
+		Ok(target) => path.parent().map(|v| v.join(&target)).unwrap_or(target),
+		Err(_) => path.to_path_buf()
+	}
+}
+
+/// Truncates and rewrites the file. Not atomic, so only used if renaming isn't possible.
+fn write_in_place(path: &Path, contents: &[u8]) -> Result<()> {
+	let mut file = OpenOptions::new().write(true).truncate(true).open(path)
+		.with_context(|| format!("Unable to write {}", path.display()))?;
+
+	file.write_all(contents)?;
+	file.sync_all()?;
+
+	Ok(())
+}
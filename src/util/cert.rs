@@ -0,0 +1,188 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use anyhow::{Result, Context};
+use rcgen::{
+	BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+	KeyUsagePurpose, SanType
+};
+use time::{Duration, OffsetDateTime};
+use x509_parser::{extensions::GeneralName, pem::parse_x509_pem};
+
+use crate::{Error, write_atomic, write_private};
+
+
+/// Days a leaf certificate is valid for. Browsers refuse anything longer than 398.
+pub const LEAF_VALIDITY_DAYS: i64 = 397;
+/// Days the root certificate is valid for.
+pub const ROOT_VALIDITY_DAYS: i64 = 3650;
+/// Leaf certificates are renewed once they expire within this many days.
+pub const RENEW_BEFORE_DAYS: i64 = 30;
+
+const ROOT_NAME: &str = "localhosting Root CA";
+
+
+/// Local certificate authority and the leaf certificates it issued.
+///
+/// The root is stored as "root.pem" and "root.key", leafs as "<host>.pem" and "<host>.key" next to it.
+#[derive(Debug, Clone)]
+pub struct CertStore {
+	dir: PathBuf
+}
+
+impl CertStore {
+	/// Default location inside the users' config directory.
+	pub fn default_dir() -> Result<PathBuf> {
+		let dir = dirs::config_dir().context("Unable to find config directory")?;
+
+		Ok(dir.join("localhosting").join("certs"))
+	}
+
+	pub fn new(dir: PathBuf) -> Self {
+		Self {
+			dir
+		}
+	}
+
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	pub fn root_path(&self) -> PathBuf {
+		self.dir.join("root.pem")
+	}
+
+	fn root_key_path(&self) -> PathBuf {
+		self.dir.join("root.key")
+	}
+
+	/// Certificate and key file of the host. The host is the first name of the certificate.
+	pub fn leaf_paths(&self, host: &str) -> (PathBuf, PathBuf) {
+		// Wildcards aren't allowed in every file system.
+		let stem = host.replace('*', "_wildcard");
+
+		(self.dir.join(format!("{}.pem", stem)), self.dir.join(format!("{}.key", stem)))
+	}
+
+	/// Loads the root, creating it first if it doesn't exist. Returns whether it was created.
+	pub fn root(&self, now: OffsetDateTime) -> Result<(Certificate, bool)> {
+		let (cert_path, key_path) = (self.root_path(), self.root_key_path());
+
+		if cert_path.exists() && key_path.exists() {
+			let key = KeyPair::from_pem(&fs::read_to_string(&key_path)?)?;
+			let params = CertificateParams::from_ca_cert_pem(&fs::read_to_string(&cert_path)?, key)?;
+
+			return Ok((Certificate::from_params(params)?, false));
+		}
+
+		let mut name = DistinguishedName::new();
+		name.push(DnType::CommonName, ROOT_NAME);
+		name.push(DnType::OrganizationName, "localhosting");
+
+		let mut params = CertificateParams::default();
+		params.distinguished_name = name;
+		params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+		params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign, KeyUsagePurpose::DigitalSignature];
+		params.serial_number = Some(rand::random());
+		params.not_before = now - Duration::days(1);
+		params.not_after = now + Duration::days(ROOT_VALIDITY_DAYS);
+
+		let root = Certificate::from_params(params)?;
+
+		fs::create_dir_all(&self.dir)?;
+		write_private(&key_path, root.serialize_private_key_pem())?;
+		write_atomic(&cert_path, &root.serialize_pem()?)?;
+
+		Ok((root, true))
+	}
+
+	/// Root certificate in PEM.
+	pub fn root_pem(&self) -> Result<String> {
+		fs::read_to_string(self.root_path())
+			.map_err(|_| Error::NotFound(self.root_path().display().to_string()).into())
+	}
+
+	/// Root certificate in DER.
+	pub fn root_der(&self) -> Result<Vec<u8>> {
+		let pem = self.root_pem()?;
+		let (_, pem) = parse_x509_pem(pem.as_bytes()).map_err(|e| Error::parse("root.pem", e))?;
+
+		Ok(pem.contents)
+	}
+
+	/// Issues a certificate for the names, replacing the existing one. The first name is the host.
+	pub fn issue(&self, root: &Certificate, names: &[String], now: OffsetDateTime) -> Result<Leaf> {
+		let mut name = DistinguishedName::new();
+		name.push(DnType::CommonName, names[0].as_str());
+
+		let mut params = CertificateParams::default();
+		params.distinguished_name = name;
+		params.subject_alt_names = names.iter().map(|v| SanType::DnsName(v.clone())).collect();
+		params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+		params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+		params.use_authority_key_identifier_extension = true;
+		params.serial_number = Some(rand::random());
+		params.not_before = now - Duration::days(1);
+		params.not_after = now + Duration::days(LEAF_VALIDITY_DAYS);
+
+		let leaf = Certificate::from_params(params)?;
+		let (cert_path, key_path) = self.leaf_paths(&names[0]);
+
+		fs::create_dir_all(&self.dir)?;
+		write_private(&key_path, leaf.serialize_private_key_pem())?;
+		write_atomic(&cert_path, &leaf.serialize_pem_with_signer(root)?)?;
+
+		self.leaf(&names[0])?.ok_or_else(|| Error::NotFound(cert_path.display().to_string()).into())
+	}
+
+	/// Issued certificate of the host. None if there's none.
+	pub fn leaf(&self, host: &str) -> Result<Option<Leaf>> {
+		let (cert_path, key_path) = self.leaf_paths(host);
+
+		if !cert_path.exists() || !key_path.exists() {
+			return Ok(None);
+		}
+
+		let pem = fs::read(&cert_path)?;
+		let (_, pem) = parse_x509_pem(&pem).map_err(|e| Error::parse(&cert_path.display().to_string(), e))?;
+		let cert = pem.parse_x509().map_err(|e| Error::parse(&cert_path.display().to_string(), e))?;
+
+		let names = cert.subject_alternative_name()
+			.ok()
+			.flatten()
+			.map(|v| v.value.general_names.iter()
+				.filter_map(|v| match v {
+					GeneralName::DNSName(name) => Some(name.to_string()),
+					_ => None
+				})
+				.collect())
+			.unwrap_or_default();
+
+		Ok(Some(Leaf {
+			host: host.to_string(),
+			names,
+			not_after: cert.validity().not_after.timestamp(),
+			cert_path,
+			key_path
+		}))
+	}
+}
+
+
+/// Issued leaf certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leaf {
+	pub host: String,
+	/// Subject alternative names. The host followed by its' aliases.
+	pub names: Vec<String>,
+	/// Unix timestamp (seconds) of when it expires.
+	pub not_after: i64,
+	pub cert_path: PathBuf,
+	pub key_path: PathBuf
+}
+
+impl Leaf {
+	/// Whether it expires within `RENEW_BEFORE_DAYS`.
+	pub fn needs_renewal(&self, now: OffsetDateTime) -> bool {
+		self.not_after - now.unix_timestamp() < Duration::days(RENEW_BEFORE_DAYS).whole_seconds()
+	}
+}
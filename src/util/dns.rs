@@ -0,0 +1,482 @@
+use std::{
+	fs,
+	io::{Read, Write},
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+	path::{Path, PathBuf},
+	sync::{Arc, RwLock},
+	thread,
+	time::Duration
+};
+
+use anyhow::{Result, bail};
+
+use crate::{Connection, Error, State};
+
+
+/// Default address of the DNS responder.
+pub const DEFAULT_DNS_LISTEN: &str = "127.0.0.1:53";
+
+/// Seconds answers may be cached for. Kept short since mappings change often.
+const TTL: u32 = 60;
+/// How long the upstream resolver and TCP clients are waited on.
+const TIMEOUT: Duration = Duration::from_secs(3);
+/// How often the state file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+pub const RCODE_NO_ERROR: u8 = 0;
+pub const RCODE_FORMAT_ERROR: u8 = 1;
+pub const RCODE_SERVER_FAILURE: u8 = 2;
+pub const RCODE_REFUSED: u8 = 5;
+
+
+/// Record types which are answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+	A,
+	Aaaa
+}
+
+impl RecordType {
+	pub fn code(self) -> u16 {
+		match self {
+			Self::A => TYPE_A,
+			Self::Aaaa => TYPE_AAAA
+		}
+	}
+
+	fn matches(self, address: &IpAddr) -> bool {
+		match self {
+			Self::A => address.is_ipv4(),
+			Self::Aaaa => address.is_ipv6()
+		}
+	}
+}
+
+
+/// Managed host names and the listening address they resolve to.
+///
+/// A name starting with "*." matches every subdomain of the rest. Exact names win over wildcards, longer wildcards over shorter ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Zone {
+	pub records: Vec<(String, IpAddr)>
+}
+
+impl Zone {
+	pub fn from_state(state: &State) -> Self {
+		let mut records = Vec::new();
+
+		for mapping in &state.mappings {
+			for host in &mapping.hosts {
+				let record = (host.to_ascii_lowercase(), mapping.listen_to.address);
+
+				// Every port of an address has a mapping with the same hosts.
+				if !records.contains(&record) {
+					records.push(record);
+				}
+			}
+		}
+
+		Self {
+			records
+		}
+	}
+
+	/// Addresses of the name. None if the name isn't managed.
+	///
+	/// Managed names without an address of the type return an empty list.
+	pub fn lookup(&self, name: &str, record_type: RecordType) -> Option<Vec<IpAddr>> {
+		let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+		let exact = self.records.iter()
+			.filter(|(host, _)| *host == name)
+			.collect::<Vec<_>>();
+
+		let found = if exact.is_empty() {
+			let longest = self.records.iter()
+				.filter_map(|(host, _)| host.strip_prefix("*."))
+				.filter(|suffix| name.ends_with(&format!(".{}", suffix)))
+				.max_by_key(|v| v.len())?;
+
+			self.records.iter()
+				.filter(|(host, _)| host.strip_prefix("*.") == Some(longest))
+				.collect()
+		} else {
+			exact
+		};
+
+		Some(found.into_iter()
+			.map(|(_, address)| *address)
+			.filter(|v| record_type.matches(v))
+			.collect())
+	}
+
+	/// Answers the query packet. Names which aren't managed are forwarded to `upstream` or refused.
+	pub fn respond(&self, packet: &[u8], upstream: Option<SocketAddr>) -> Vec<u8> {
+		let question = match Question::parse(packet) {
+			Ok(v) => v,
+			Err(_) if packet.len() >= 2 => return response(packet, None, RCODE_FORMAT_ERROR, &[]),
+			Err(_) => return Vec::new()
+		};
+
+		let record_type = match question.qtype {
+			TYPE_A => Some(RecordType::A),
+			TYPE_AAAA => Some(RecordType::Aaaa),
+			_ => None
+		};
+
+		let answers = match record_type {
+			Some(v) if question.qclass == CLASS_IN => self.lookup(&question.name, v),
+			// Other record types of a managed name have no data.
+			_ if self.lookup(&question.name, RecordType::A).is_some() => Some(Vec::new()),
+			_ => None
+		};
+
+		match (answers, upstream) {
+			(Some(answers), _) => response(packet, Some(&question), RCODE_NO_ERROR, &answers),
+			(None, Some(upstream)) => forward(packet, upstream)
+				.unwrap_or_else(|_| response(packet, Some(&question), RCODE_SERVER_FAILURE, &[])),
+			(None, None) => response(packet, Some(&question), RCODE_REFUSED, &[])
+		}
+	}
+}
+
+
+/// Answers queries for a zone over UDP and TCP.
+pub struct DnsServer {
+	zone: Arc<RwLock<Arc<Zone>>>,
+	upstream: Option<SocketAddr>,
+	udp: UdpSocket,
+	tcp: TcpListener,
+	/// State file the zone is rebuilt from whenever it changes.
+	watching: Option<PathBuf>
+}
+
+impl DnsServer {
+	/// Binds both sockets. Only loopback addresses are allowed so the responder is never reachable from outside.
+	///
+	/// A port of 0 picks a free one. UDP and TCP then use the same port.
+	pub fn bind(zone: Zone, listen: Connection, upstream: Option<Connection>) -> Result<Self> {
+		if !listen.address.is_loopback() {
+			return Err(Error::parse(&listen.to_string(), "Expected a loopback address").into());
+		}
+
+		let tcp = TcpListener::bind(SocketAddr::from(listen))?;
+		let udp = UdpSocket::bind(tcp.local_addr()?)?;
+
+		Ok(Self {
+			zone: Arc::new(RwLock::new(Arc::new(zone))),
+			upstream: upstream.map(SocketAddr::from),
+			udp,
+			tcp,
+			watching: None
+		})
+	}
+
+	/// Rebuilds the zone from the state file whenever it changes, so added hosts are answered without a restart.
+	pub fn watch(mut self, state_path: PathBuf) -> Self {
+		self.watching = Some(state_path);
+		self
+	}
+
+	pub fn local_addr(&self) -> Result<SocketAddr> {
+		Ok(self.tcp.local_addr()?)
+	}
+
+	/// Answers queries until the process is stopped.
+	pub fn run(self) -> Result<()> {
+		if let Some(path) = self.watching {
+			let zone = self.zone.clone();
+			thread::spawn(move || reload(&path, &zone));
+		}
+
+		let (zone, upstream) = (self.zone.clone(), self.upstream);
+		let udp = self.udp;
+
+		thread::spawn(move || {
+			let mut buffer = [0; 512];
+
+			loop {
+				let (len, from) = match udp.recv_from(&mut buffer) {
+					Ok(v) => v,
+					Err(_) => continue
+				};
+
+				let (zone, packet) = (current(&zone), buffer[..len].to_vec());
+
+				// Forwarding waits on the upstream resolver, which mustn't hold up other queries.
+				let udp = match udp.try_clone() {
+					Ok(v) => v,
+					Err(_) => continue
+				};
+
+				thread::spawn(move || {
+					let reply = zone.respond(&packet, upstream);
+
+					if !reply.is_empty() {
+						udp.send_to(&reply, from).ok();
+					}
+				});
+			}
+		});
+
+		for stream in self.tcp.incoming() {
+			let (zone, upstream) = (current(&self.zone), self.upstream);
+
+			if let Ok(stream) = stream {
+				thread::spawn(move || respond_tcp(stream, &zone, upstream));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Runs on a background thread. Used to query it from the same process.
+	pub fn spawn(self) -> thread::JoinHandle<Result<()>> {
+		thread::spawn(move || self.run())
+	}
+}
+
+
+/// Question of a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Question {
+	name: String,
+	qtype: u16,
+	qclass: u16,
+	/// Offset after the question.
+	end: usize
+}
+
+impl Question {
+	fn parse(packet: &[u8]) -> Result<Self> {
+		if packet.len() < 12 || read_u16(packet, 4)? != 1 {
+			bail!("Expected a single question");
+		}
+
+		// Only standard queries.
+		if packet[2] & 0x80 != 0 || (packet[2] >> 3) & 0x0F != 0 {
+			bail!("Not a query");
+		}
+
+		let (name, offset) = read_name(packet, 12)?;
+
+		Ok(Self {
+			name,
+			qtype: read_u16(packet, offset)?,
+			qclass: read_u16(packet, offset + 2)?,
+			end: offset + 4
+		})
+	}
+}
+
+
+/// Query packet for the name.
+pub fn query(id: u16, name: &str, record_type: RecordType) -> Vec<u8> {
+	let mut packet = Vec::new();
+
+	packet.extend(id.to_be_bytes());
+	// Recursion desired.
+	packet.extend([0x01, 0x00]);
+	packet.extend([0, 1, 0, 0, 0, 0, 0, 0]);
+
+	for label in name.trim_end_matches('.').split('.').filter(|v| !v.is_empty()) {
+		packet.push(label.len() as u8);
+		packet.extend(label.as_bytes());
+	}
+
+	packet.push(0);
+	packet.extend(record_type.code().to_be_bytes());
+	packet.extend(CLASS_IN.to_be_bytes());
+
+	packet
+}
+
+/// Response code and every A/AAAA address of a response packet.
+pub fn parse_response(packet: &[u8]) -> Result<(u8, Vec<IpAddr>)> {
+	if packet.len() < 12 || packet[2] & 0x80 == 0 {
+		bail!("Not a response");
+	}
+
+	let rcode = packet[3] & 0x0F;
+	let questions = read_u16(packet, 4)?;
+	let answers = read_u16(packet, 6)?;
+
+	let mut offset = 12;
+
+	for _ in 0..questions {
+		offset = read_name(packet, offset)?.1 + 4;
+	}
+
+	let mut addresses = Vec::new();
+
+	for _ in 0..answers {
+		offset = read_name(packet, offset)?.1;
+
+		let rtype = read_u16(packet, offset)?;
+		let len = read_u16(packet, offset + 8)? as usize;
+		let data = packet.get(offset + 10..offset + 10 + len).ok_or_else(|| Error::parse("DNS response", "Truncated answer"))?;
+
+		match (rtype, len) {
+			(TYPE_A, 4) => addresses.push(IpAddr::from(Ipv4Addr::new(data[0], data[1], data[2], data[3]))),
+			(TYPE_AAAA, 16) => addresses.push(IpAddr::from(Ipv6Addr::from(<[u8; 16]>::try_from(data)?))),
+			_ => ()
+		}
+
+		offset += 10 + len;
+	}
+
+	Ok((rcode, addresses))
+}
+
+
+/// Response to the query. The question is copied and answers point back to its' name.
+fn response(packet: &[u8], question: Option<&Question>, rcode: u8, answers: &[IpAddr]) -> Vec<u8> {
+	let mut reply = Vec::new();
+
+	reply.extend(&packet[..2]);
+	// Response, same opcode and recursion desired flag, authoritative if answered.
+	reply.push(0x80 | (packet.get(2).copied().unwrap_or_default() & 0x79) | if rcode == RCODE_NO_ERROR { 0x04 } else { 0 });
+	reply.push(rcode);
+
+	let question = match question {
+		Some(v) => v,
+		None => {
+			reply.extend([0; 8]);
+			return reply;
+		}
+	};
+
+	reply.extend([0, 1]);
+	reply.extend((answers.len() as u16).to_be_bytes());
+	reply.extend([0, 0, 0, 0]);
+	reply.extend(&packet[12..question.end]);
+
+	for address in answers {
+		// Pointer to the name of the question.
+		reply.extend([0xC0, 12]);
+
+		let data = match address {
+			IpAddr::V4(v) => v.octets().to_vec(),
+			IpAddr::V6(v) => v.octets().to_vec()
+		};
+
+		reply.extend(if address.is_ipv4() { TYPE_A } else { TYPE_AAAA }.to_be_bytes());
+		reply.extend(CLASS_IN.to_be_bytes());
+		reply.extend(TTL.to_be_bytes());
+		reply.extend((data.len() as u16).to_be_bytes());
+		reply.extend(data);
+	}
+
+	reply
+}
+
+fn current(zone: &RwLock<Arc<Zone>>) -> Arc<Zone> {
+	zone.read().map(|v| v.clone()).unwrap_or_default()
+}
+
+/// Replaces the zone every time the state files' modification time changes. Unreadable states are skipped.
+fn reload(path: &Path, zone: &RwLock<Arc<Zone>>) {
+	let modified = || fs::metadata(path).and_then(|v| v.modified()).ok();
+	// Reloaded once at first, the state may have changed between creating the zone and starting to watch.
+	let mut last = None;
+
+	loop {
+		thread::sleep(RELOAD_INTERVAL);
+
+		let current = modified();
+
+		if current == last {
+			continue;
+		}
+
+		last = current;
+
+		if let (Ok(Some(state)), Ok(mut zone)) = (State::load(path.to_path_buf()), zone.write()) {
+			*zone = Arc::new(Zone::from_state(&state));
+		}
+	}
+}
+
+/// Sends the query to the upstream resolver as-is.
+fn forward(packet: &[u8], upstream: SocketAddr) -> Result<Vec<u8>> {
+	let local: SocketAddr = if upstream.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { (Ipv6Addr::UNSPECIFIED, 0).into() };
+
+	let socket = UdpSocket::bind(local)?;
+	socket.set_read_timeout(Some(TIMEOUT))?;
+	socket.send_to(packet, upstream)?;
+
+	let mut buffer = [0; 4096];
+
+	loop {
+		let (len, from) = socket.recv_from(&mut buffer)?;
+
+		// Ignore anything which isn't the reply.
+		if from == upstream && len >= 2 && buffer[..2] == packet[..2] {
+			return Ok(buffer[..len].to_vec());
+		}
+	}
+}
+
+/// Every TCP message is prefixed with its' length.
+fn respond_tcp(mut stream: TcpStream, zone: &Zone, upstream: Option<SocketAddr>) -> Result<()> {
+	stream.set_read_timeout(Some(TIMEOUT))?;
+
+	loop {
+		let mut len = [0; 2];
+
+		if stream.read_exact(&mut len).is_err() {
+			return Ok(());
+		}
+
+		let mut packet = vec![0; u16::from_be_bytes(len) as usize];
+		stream.read_exact(&mut packet)?;
+
+		let reply = zone.respond(&packet, upstream);
+
+		if reply.is_empty() {
+			return Ok(());
+		}
+
+		stream.write_all(&(reply.len() as u16).to_be_bytes())?;
+		stream.write_all(&reply)?;
+	}
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> Result<u16> {
+	match packet.get(offset..offset + 2) {
+		Some(v) => Ok(u16::from_be_bytes([v[0], v[1]])),
+		None => Err(Error::parse("DNS packet", "Truncated").into())
+	}
+}
+
+/// Reads the name at the offset, following compression pointers. Returns the offset after it.
+fn read_name(packet: &[u8], mut offset: usize) -> Result<(String, usize)> {
+	let mut labels = Vec::new();
+	let mut end = None;
+
+	// Limits pointer loops.
+	for _ in 0..128 {
+		let len = *packet.get(offset).ok_or_else(|| Error::parse("DNS packet", "Truncated name"))? as usize;
+
+		if len == 0 {
+			return Ok((labels.join("."), end.unwrap_or(offset + 1)));
+		}
+
+		if len & 0xC0 == 0xC0 {
+			end.get_or_insert(offset + 2);
+			offset = (read_u16(packet, offset)? & 0x3FFF) as usize;
+			continue;
+		}
+
+		let label = packet.get(offset + 1..offset + 1 + len).ok_or_else(|| Error::parse("DNS packet", "Truncated label"))?;
+		labels.push(String::from_utf8_lossy(label).to_string());
+
+		offset += 1 + len;
+	}
+
+	Err(Error::parse("DNS packet", "Name too long").into())
+}
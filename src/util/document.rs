@@ -0,0 +1,256 @@
+use std::{fmt::{self, Display}, net::IpAddr, ops::Range, str::FromStr};
+
+use anyhow::Result;
+
+use crate::HostItem;
+
+
+const COMMENT_CHARACTER: char = '#';
+
+/// Start of the block which localhosting owns. Nothing outside of it is ever modified.
+pub const BLOCK_BEGIN: &str = "# BEGIN localhosting";
+/// End of the block which localhosting owns.
+pub const BLOCK_END: &str = "# END localhosting";
+
+
+/// Every line of a hosts file. Writing it back out results in the exact same contents unless modified.
+#[derive(Debug, Default, Clone)]
+pub struct HostsDocument {
+	pub lines: Vec<Line>
+}
+
+impl HostsDocument {
+	/// Range of the lines between the block markers. Excludes the markers themselves.
+	pub fn block_range(&self) -> Option<Range<usize>> {
+		let begin = self.lines.iter().position(|v| v.is_marker(BLOCK_BEGIN))?;
+
+		let end = self.lines[begin + 1..].iter()
+			.position(|v| v.is_marker(BLOCK_END))
+			.map(|v| v + begin + 1)
+			.unwrap_or(self.lines.len());
+
+		Some(begin + 1..end)
+	}
+
+	pub fn items(&self) -> Vec<HostItem> {
+		let block = self.block_range().unwrap_or_default();
+
+		self.lines.iter()
+			.enumerate()
+			.filter_map(|(index, line)| {
+				let entry = line.entry()?;
+
+				let (host, aliases) = entry.hosts.split_first()?;
+
+				Some(HostItem {
+					address: entry.address,
+					host: host.clone(),
+					aliases: aliases.to_vec(),
+					managed: block.contains(&index)
+				})
+			})
+			.collect()
+	}
+
+	/// Adds the entry to the end of the managed block, creating the block if needed.
+	pub fn push_managed(&mut self, entry: Entry) {
+		let ending = self.line_ending().to_string();
+
+		// Ensure the last line is terminated before appending anything to it.
+		if let Some(last) = self.lines.last_mut() {
+			if last.ending.is_empty() {
+				last.ending = ending.clone();
+			}
+		}
+
+		let range = match self.block_range() {
+			Some(v) => v,
+			None => {
+				self.lines.push(Line::new(BLOCK_BEGIN.to_string(), LineKind::Comment, ending.clone()));
+				self.lines.len()..self.lines.len()
+			}
+		};
+
+		// The block was never closed.
+		if range.end == self.lines.len() {
+			self.lines.push(Line::new(BLOCK_END.to_string(), LineKind::Comment, ending.clone()));
+		}
+
+		self.lines.insert(range.end, Line::new(entry.to_string(), LineKind::Entry(entry), ending));
+	}
+
+	/// Edits the managed entries, removing the ones `f` returns false for. The block is removed entirely once it's empty.
+	pub fn edit_managed<F: FnMut(&mut Entry) -> bool>(&mut self, mut f: F) {
+		let range = match self.block_range() {
+			Some(v) => v,
+			None => return
+		};
+
+		let mut index = range.start;
+		let mut end = range.end;
+
+		while index < end {
+			let line = &mut self.lines[index];
+
+			if let LineKind::Entry(entry) = &mut line.kind {
+				let previous = entry.clone();
+
+				if !f(entry) {
+					self.lines.remove(index);
+					end -= 1;
+					continue;
+				}
+
+				// Only regenerate the text if it was changed.
+				if previous != *entry {
+					line.text = entry.to_string();
+				}
+			}
+
+			index += 1;
+		}
+
+		if range.start == end {
+			let has_end_marker = end < self.lines.len();
+
+			self.lines.drain(range.start - 1..if has_end_marker { end + 1 } else { end });
+		}
+	}
+
+	/// Line ending used by the document. Defaults to "\n".
+	fn line_ending(&self) -> &str {
+		self.lines.iter()
+			.map(|v| v.ending.as_str())
+			.find(|v| !v.is_empty())
+			.unwrap_or("\n")
+	}
+}
+
+impl FromStr for HostsDocument {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut lines = Vec::new();
+
+		for value in s.split_inclusive('\n') {
+			let (text, ending) = if let Some(v) = value.strip_suffix("\r\n") {
+				(v, "\r\n")
+			} else if let Some(v) = value.strip_suffix('\n') {
+				(v, "\n")
+			} else {
+				(value, "")
+			};
+
+			lines.push(Line::new(text.to_string(), LineKind::parse(text), ending.to_string()));
+		}
+
+		Ok(Self { lines })
+	}
+}
+
+impl Display for HostsDocument {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for line in &self.lines {
+			write!(f, "{}{}", line.text, line.ending)?;
+		}
+
+		Ok(())
+	}
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Line {
+	/// Original text, excluding the line ending.
+	pub text: String,
+	pub kind: LineKind,
+	/// "\n", "\r\n" or empty for the last line.
+	pub ending: String
+}
+
+impl Line {
+	pub fn new(text: String, kind: LineKind, ending: String) -> Self {
+		Self { text, kind, ending }
+	}
+
+	pub fn entry(&self) -> Option<&Entry> {
+		match &self.kind {
+			LineKind::Entry(v) => Some(v),
+			_ => None
+		}
+	}
+
+	fn is_marker(&self, marker: &str) -> bool {
+		matches!(self.kind, LineKind::Comment) && self.text.trim() == marker
+	}
+}
+
+
+#[derive(Debug, Clone)]
+pub enum LineKind {
+	/// Empty or whitespace only.
+	Blank,
+	/// Starts with a "#".
+	Comment,
+	Entry(Entry),
+	/// Anything which isn't understood, like zone ids ("fe80::1%lo0 localhost"). Kept exactly as it is.
+	Other
+}
+
+impl LineKind {
+	fn parse(line: &str) -> Self {
+		let trimmed = line.trim();
+
+		if trimmed.is_empty() {
+			return Self::Blank;
+		}
+
+		if trimmed.starts_with(COMMENT_CHARACTER) {
+			return Self::Comment;
+		}
+
+		// Split off the inline comment.
+		let (value, comment) = match line.split_once(COMMENT_CHARACTER) {
+			Some((value, comment)) => (value, Some(comment.trim().to_string())),
+			None => (line, None)
+		};
+
+		let mut split = value.split_ascii_whitespace();
+
+		let address = match split.next().unwrap_or_default().parse() {
+			Ok(v) => v,
+			Err(_) => return Self::Other
+		};
+
+		Self::Entry(Entry {
+			address,
+			hosts: split.map(|v| v.to_string()).collect(),
+			comment
+		})
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+	pub address: IpAddr,
+	/// Host name followed by its' aliases.
+	pub hosts: Vec<String>,
+	pub comment: Option<String>
+}
+
+impl Display for Entry {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.address)?;
+
+		for host in &self.hosts {
+			write!(f, " {}", host)?;
+		}
+
+		if let Some(comment) = &self.comment {
+			write!(f, " {} {}", COMMENT_CHARACTER, comment)?;
+		}
+
+		Ok(())
+	}
+}
@@ -0,0 +1,107 @@
+use std::{fmt, path::PathBuf, process::Output};
+
+use crate::{Connection, PortOwner};
+
+
+/// How to get the rights to modify system files on this platform.
+#[cfg(windows)]
+pub const ELEVATION_HINT: &str = "Please run as Administrator";
+#[cfg(not(windows))]
+pub const ELEVATION_HINT: &str = "Please run as root (sudo)";
+
+
+/// Errors which scripts wrapping localhosting may want to handle. Everything is still propagated through `anyhow`.
+#[derive(Debug)]
+pub enum Error {
+	/// An external program (netsh, nft, iptables) returned a non-zero exit code.
+	BackendFailed {
+		program: String,
+		action: &'static str,
+		status: String,
+		stdout: String,
+		stderr: String
+	},
+	/// Missing rights to modify the file. (Not running as Administrator/root)
+	PermissionDenied(PathBuf),
+	/// Unable to parse the value.
+	Parse {
+		value: String,
+		reason: String
+	},
+	/// The host is already using a different bridge.
+	DuplicateBridge {
+		host: String,
+		connect_to: Connection
+	},
+	/// Nothing matched the Address or Host.
+	NotFound(String),
+	/// Every address of the subnet is in use.
+	Exhausted(String),
+	/// Something else is already listening on the connection.
+	PortInUse {
+		connection: Connection,
+		owner: Option<PortOwner>
+	}
+}
+
+impl Error {
+	pub fn backend(program: &str, action: &'static str, output: &Output) -> Self {
+		Self::BackendFailed {
+			program: program.to_string(),
+			action,
+			status: output.status.to_string(),
+			stdout: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+			stderr: String::from_utf8_lossy(&output.stderr).trim().to_string()
+		}
+	}
+
+	pub fn parse(value: &str, reason: impl ToString) -> Self {
+		Self::Parse {
+			value: value.to_string(),
+			reason: reason.to_string()
+		}
+	}
+
+	/// Exit code used for the error. Based on the BSD sysexits.
+	pub fn exit_code(&self) -> i32 {
+		match self {
+			Self::BackendFailed { .. } => 71,
+			Self::PermissionDenied(_) => 77,
+			Self::Parse { .. } => 65,
+			Self::DuplicateBridge { .. } => 73,
+			Self::NotFound(_) => 66,
+			Self::Exhausted(_) => 69,
+			Self::PortInUse { .. } => 75
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::BackendFailed { program, action, status, stdout, stderr } => {
+				write!(f, "[{}][{}]: {}", program, action, status)?;
+
+				if !stdout.is_empty() {
+					write!(f, "\nstdout: {}", stdout)?;
+				}
+
+				if !stderr.is_empty() {
+					write!(f, "\nstderr: {}", stderr)?;
+				}
+
+				Ok(())
+			}
+
+			Self::PermissionDenied(path) => write!(f, "Unable to modify {}. {}.", path.display(), ELEVATION_HINT),
+			Self::Parse { value, reason } => write!(f, "Unable to parse {:?}: {}", value, reason),
+			Self::DuplicateBridge { host, connect_to } => write!(f, "{:?} is already bridged to {}", host, connect_to),
+			Self::NotFound(value) => write!(f, "Unable to find {:?}", value),
+			Self::Exhausted(subnet) => write!(f, "No unused listening addresses left in {}", subnet),
+			Self::PortInUse { connection, owner: Some(owner) } => write!(f, "{} is already in use by {}", connection, owner),
+			Self::PortInUse { connection, owner: None } => write!(f, "{} is already in use", connection)
+		}
+	}
+}
+
+impl std::error::Error for Error {}
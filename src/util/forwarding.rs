@@ -0,0 +1,83 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::Connection;
+
+
+/// Something which is able to forward an allocated listening address to a known connection.
+///
+/// Implementors only have to manage the bridges themselves, addresses are allocated by the state.
+pub trait ForwardingBackend {
+	/// Currently registered bridges.
+	fn bridges(&self) -> &[Bridge];
+
+	/// Register the bridge with the backend.
+	fn add_bridge(&mut self, bridge: Bridge) -> Result<()>;
+
+	/// Unregister the bridge from the backend.
+	fn delete_bridge(&mut self, bridge: &Bridge) -> Result<()>;
+
+	/// Commands `add_bridge` would run while `current` are registered. Nothing is run.
+	fn plan_add_bridge(&self, _current: &[Bridge], _bridge: &Bridge) -> Vec<String> {
+		Vec::new()
+	}
+
+	/// Commands `delete_bridge` would run while `current` are registered. Nothing is run.
+	fn plan_delete_bridge(&self, _current: &[Bridge], _bridge: &Bridge) -> Vec<String> {
+		Vec::new()
+	}
+
+
+	fn contains(&self, value: Connection) -> bool {
+		self.bridges().iter().any(|v| v.connect_to == value || v.listen_to == value)
+	}
+
+	fn get_by_connection(&self, value: Connection) -> Option<&Bridge> {
+		self.bridges().iter().find(|v| v.connect_to == value || v.listen_to == value)
+	}
+
+	fn delete(&mut self, connect_to: Connection) -> Result<Option<Bridge>> {
+		if let Some(bridge) = self.get_by_connection(connect_to).cloned() {
+			self.delete_bridge(&bridge)?;
+
+			Ok(Some(bridge))
+		} else {
+			Ok(None)
+		}
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bridge {
+	/// Allocated backend 127.*.*.* Ip Address.
+	pub listen_to: Connection,
+	/// Known IP Address used to connect to the Host Proxy.
+	pub connect_to: Connection
+}
+
+
+/// Keeps bridges in memory only. Used for tests.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+	pub bridges: Vec<Bridge>
+}
+
+impl ForwardingBackend for MemoryBackend {
+	fn bridges(&self) -> &[Bridge] {
+		&self.bridges
+	}
+
+	fn add_bridge(&mut self, bridge: Bridge) -> Result<()> {
+		self.bridges.push(bridge);
+
+		Ok(())
+	}
+
+	fn delete_bridge(&mut self, bridge: &Bridge) -> Result<()> {
+		self.bridges.retain(|v| v.listen_to != bridge.listen_to);
+
+		Ok(())
+	}
+}
+
@@ -1,154 +1,302 @@
-use std::{fs, net::Ipv4Addr, path::Path};
+use std::{env, fmt, fs, net::IpAddr, path::{Path, PathBuf}};
 
 use faccess::{AccessMode, PathExt};
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
+use crate::{Backups, Entry, HostsDocument, write_atomic};
 
-pub const HOSTS_FILE_PATH: &str = "C:/Windows/System32/drivers/etc/hosts";
 
-const COMMENT_CHARACTER: char = '#';
+/// Environment variable which overrides the hosts file location.
+pub const HOSTS_FILE_ENV: &str = "LOCALHOSTING_HOSTS_FILE";
 
 
+/// Where the hosts file contents are read from and written to.
+pub trait HostsStore: fmt::Debug {
+	fn read(&self) -> Result<String>;
+
+	fn write(&mut self, contents: &str) -> Result<()>;
+}
+
+
+/// Hosts file stored on disk.
+#[derive(Debug)]
+pub struct FileStore {
+	pub path: PathBuf,
+	/// Where the previous contents are backed up to before every write.
+	pub backups: Option<Backups>
+}
+
+impl FileStore {
+	pub fn new(path: PathBuf) -> Self {
+		Self {
+			path,
+			backups: None
+		}
+	}
+
+	pub fn with_backups(mut self, backups: Backups) -> Self {
+		self.backups = Some(backups);
+		self
+	}
+}
+
+impl HostsStore for FileStore {
+	fn read(&self) -> Result<String> {
+		Ok(fs::read_to_string(&self.path)?)
+	}
+
+	fn write(&mut self, contents: &str) -> Result<()> {
+		if let Some(backups) = &self.backups {
+			if self.path.exists() {
+				backups.create(&fs::read_to_string(&self.path)?)?;
+			}
+		}
+
+		write_atomic(&self.path, contents)
+	}
+}
+
+
+/// Hosts file kept in memory only. Used for tests.
 #[derive(Debug, Default)]
+pub struct MemoryStore {
+	pub contents: String
+}
+
+impl HostsStore for MemoryStore {
+	fn read(&self) -> Result<String> {
+		Ok(self.contents.clone())
+	}
+
+	fn write(&mut self, contents: &str) -> Result<()> {
+		self.contents = contents.to_string();
+		Ok(())
+	}
+}
+
+
+
+#[derive(Debug)]
 pub struct HostFile {
-	/// Check to see if we should use the windows host file. Used for tests.
-	pub uses_host_file: bool,
-	pub items: Vec<HostItem> // TODO: Store line pos for better removal?
+	store: Box<dyn HostsStore>,
+	document: HostsDocument,
+	/// Entries of the document. Updated after every change.
+	pub items: Vec<HostItem>,
+	/// Add hosts onto the same line as others using the address.
+	pub group_hosts: bool
+}
+
+impl Default for HostFile {
+	fn default() -> Self {
+		Self {
+			store: Box::new(MemoryStore::default()),
+			document: HostsDocument::default(),
+			items: Vec::new(),
+			group_hosts: false
+		}
+	}
 }
 
 impl HostFile {
-	pub fn read() -> Result<Self> {
-		let value = fs::read_to_string(HOSTS_FILE_PATH)?;
+	/// Reads the hosts file from disk.
+	pub fn read(path: PathBuf) -> Result<Self> {
+		Self::load(Box::new(FileStore::new(path)))
+	}
 
-		let items = value.lines()
-			// Remove Empty AND Commented Lines
-			.filter(|v| !v.is_empty() && !v.starts_with(COMMENT_CHARACTER))
-			// Parse Lines
-			.filter_map(parse_line)
-			.collect::<Result<_>>()?;
+	pub fn load(store: Box<dyn HostsStore>) -> Result<Self> {
+		let document = store.read()?.parse::<HostsDocument>()?;
 
 		Ok(HostFile {
-			uses_host_file: true,
-			items
+			store,
+			items: document.items(),
+			document,
+			group_hosts: false
 		})
 	}
 
+	pub fn store(&self) -> &dyn HostsStore {
+		self.store.as_ref()
+	}
+
+	pub fn document(&self) -> &HostsDocument {
+		&self.document
+	}
+
 	pub fn find_item_by_host(&self, value: &str) -> Option<&HostItem> {
-		self.items.iter().find(|v| v.host == value)
+		self.items.iter().find(|v| v.has_name(value))
 	}
 
-	pub fn find_by_address(&self, value: Ipv4Addr) -> Vec<&HostItem> {
+	pub fn find_by_address(&self, value: IpAddr) -> Vec<&HostItem> {
 		self.items.iter().filter(|v| v.address == value).collect()
 	}
 
-	pub fn count_addresses(&self, value: Ipv4Addr) -> usize {
-		self.items.iter().filter(|v| v.address == value).count()
+	/// Amount of host names pointing at the address.
+	pub fn count_addresses(&self, value: IpAddr) -> usize {
+		self.items.iter()
+			.filter(|v| v.address == value)
+			.map(|v| v.names().count())
+			.sum()
 	}
 
-	pub fn add(&mut self, address: Ipv4Addr, host: String) -> Result<()> {
-		if self.uses_host_file {
-			let contents = fs::read_to_string(HOSTS_FILE_PATH)?;
+	pub fn add(&mut self, address: IpAddr, host: String) -> Result<()> {
+		let mut document = self.document.clone();
 
-			fs::write(
-				HOSTS_FILE_PATH,
-				format!(
-					"{}\n{} {} # Do NOT Remove. Added Automatically (https://github.com/Its-its/localhosting)",
-					contents,
-					address,
-					host
-				)
-			)?;
+		let mut grouped = false;
+
+		if self.group_hosts {
+			document.edit_managed(|entry| {
+				if !grouped && entry.address == address {
+					entry.hosts.push(host.clone());
+					grouped = true;
+				}
+
+				true
+			});
 		}
 
-		self.items.push(HostItem {
-			address,
-			host
-		});
+		if !grouped {
+			document.push_managed(Entry {
+				address,
+				hosts: vec![host],
+				comment: None
+			});
+		}
 
-		Ok(())
+		self.save(document)
 	}
 
+	/// Deletes matching hosts from the managed block only.
+	///
+	/// Deleting a host which shares its' line with others only removes that name from the line.
 	pub fn delete(&mut self, value: DeletionType) -> Result<Vec<HostItem>> {
-		if self.uses_host_file {
-			let file = fs::read_to_string(HOSTS_FILE_PATH)?;
+		let deleted_hosts = self.matching(&value);
+
+		if deleted_hosts.is_empty() {
+			return Ok(deleted_hosts);
+		}
 
-			let filter = |line: &str| -> bool {
-				match value {
-					DeletionType::Address(v) => !line.contains(&v.to_string()),
-					DeletionType::Host(v) => !line.contains(v)
+		let mut document = self.document.clone();
+
+		document.edit_managed(|entry| match &value {
+			DeletionType::Address(v) => &entry.address != v,
+			DeletionType::Host(v) => {
+				entry.hosts.retain(|h| !h.eq_ignore_ascii_case(v));
+				!entry.hosts.is_empty()
+			}
+			DeletionType::Entry(address, v) => {
+				if &entry.address == address {
+					entry.hosts.retain(|h| !h.eq_ignore_ascii_case(v));
 				}
-			};
 
-			let contents = file
-				.lines()
-				.filter(|v| filter(v))
-				.collect::<Vec<_>>();
+				!entry.hosts.is_empty()
+			}
+		});
 
-			fs::write(
-				HOSTS_FILE_PATH,
-				contents.join("\n")
-			)?;
-		}
+		self.save(document)?;
+
+		Ok(deleted_hosts)
+	}
 
-		// Find items which need to be deleted.
-		let deleting = self.items.iter()
-			.enumerate()
-			.filter(|(_, item)| match &value {
-				DeletionType::Address(v) => &item.address == v,
-				DeletionType::Host(v) => &item.host == v
+	/// Managed hosts which would be deleted.
+	pub fn matching(&self, value: &DeletionType) -> Vec<HostItem> {
+		self.items.iter()
+			.filter(|item| item.managed)
+			.filter_map(|item| match value {
+				DeletionType::Address(v) => (&item.address == v).then(|| item.clone()),
+				// The name as it's written in the file, which may differ in case.
+				DeletionType::Host(v) => item.find_name(v).map(|name| HostItem {
+					address: item.address,
+					host: name.to_string(),
+					aliases: Vec::new(),
+					managed: true
+				}),
+				DeletionType::Entry(address, v) => item.find_name(v).filter(|_| &item.address == address).map(|name| HostItem {
+					address: item.address,
+					host: name.to_string(),
+					aliases: Vec::new(),
+					managed: true
+				})
 			})
-			.map(|(i, _)| i)
-			.rev()
-			.collect::<Vec<_>>();
+			.collect()
+	}
 
-		let mut deleted_hosts = Vec::new();
+	/// Replaces the whole file. Used to restore backups.
+	pub fn replace(&mut self, contents: &str) -> Result<()> {
+		self.save(contents.parse()?)
+	}
 
-		for index in deleting {
-			deleted_hosts.push(self.items.remove(index));
-		}
+	/// Writes the document to the store, only replacing ours once it was successful.
+	fn save(&mut self, document: HostsDocument) -> Result<()> {
+		self.store.write(&document.to_string())?;
 
-		Ok(deleted_hosts)
+		self.items = document.items();
+		self.document = document;
+
+		Ok(())
 	}
 }
 
 
 pub enum DeletionType<'a> {
-	Address(Ipv4Addr),
-	Host(&'a str)
+	Address(IpAddr),
+	Host(&'a str),
+	/// The host, only where it points at the address.
+	Entry(IpAddr, &'a str)
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HostItem {
-	pub address: Ipv4Addr,
-	pub host: String
+	pub address: IpAddr,
+	pub host: String,
+	/// Other host names on the same line.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub aliases: Vec<String>,
+	/// Whether it's inside of the localhosting block.
+	pub managed: bool
 }
 
+impl HostItem {
+	/// Host name followed by its' aliases.
+	pub fn names(&self) -> impl Iterator<Item = &str> {
+		std::iter::once(self.host.as_str()).chain(self.aliases.iter().map(|v| v.as_str()))
+	}
 
-pub fn has_write_permissions() -> bool {
-	Path::new(HOSTS_FILE_PATH).access(AccessMode::WRITE).is_ok()
+	/// The name matching the value. Host names are case insensitive.
+	pub fn find_name(&self, value: &str) -> Option<&str> {
+		self.names().find(|v| v.eq_ignore_ascii_case(value))
+	}
+
+	pub fn has_name(&self, value: &str) -> bool {
+		self.find_name(value).is_some()
+	}
 }
 
 
-fn parse_line(line: &str) -> Option<Result<HostItem>> {
-	let mut split = line.split_ascii_whitespace();
+/// Whether the host is a wildcard ("*.example.com"). Hosts files can't express them so they're only kept in the state.
+pub fn is_wildcard(host: &str) -> bool {
+	host.starts_with("*.")
+}
 
-	let address = split.next()?.parse();
-	let host = split.next()?;
+/// Location of the operating systems' hosts file.
+pub fn system_hosts_path() -> PathBuf {
+	if cfg!(windows) {
+		let root = env::var_os("SystemRoot").unwrap_or_else(|| "C:\\Windows".into());
 
-	// Check if Host contains comment. If so, only grab text before it.
-	let host = if let Some(index) = host.find(COMMENT_CHARACTER) {
-		&host[0..index]
+		PathBuf::from(root).join("System32").join("drivers").join("etc").join("hosts")
 	} else {
-		host
-	};
+		PathBuf::from("/etc/hosts")
+	}
+}
 
-	match address {
-		Ok(address) => Some(Ok(HostItem {
-			address,
-			host: host.to_string()
-		})),
+/// Overridden path if one was specified, otherwise the "LOCALHOSTING_HOSTS_FILE" env var, otherwise the system hosts file.
+pub fn hosts_file_path(value: Option<&str>) -> PathBuf {
+	value.map(PathBuf::from)
+		.or_else(|| env::var_os(HOSTS_FILE_ENV).map(PathBuf::from))
+		.unwrap_or_else(system_hosts_path)
+}
 
-		Err(e) => Some(Err(e.into()))
-	}
-}
\ No newline at end of file
+pub fn has_write_permissions(path: &Path) -> bool {
+	path.access(AccessMode::WRITE).is_ok()
+}
@@ -0,0 +1,444 @@
+use std::{fs, net::IpAddr, path::PathBuf};
+
+use anyhow::{Result, Context, bail};
+
+use crate::{Bridge, Change, Connection, DeletionType, ForwardingBackend, HostFile, HostItem, State, is_wildcard, write_atomic};
+
+
+/// Last line of a journal whose transaction was applied completely.
+const COMMIT_LINE: &str = "commit";
+
+
+/// A single change made to either the hosts file or forwarding backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+	AddHost(IpAddr, String),
+	DeleteHost(IpAddr, String),
+	AddBridge(Bridge),
+	DeleteBridge(Bridge)
+}
+
+impl Step {
+	/// Reverts the step. Does nothing if it was never applied.
+	fn undo(&self, backend: &mut dyn ForwardingBackend, hosts: &mut HostFile) -> Result<()> {
+		match self {
+			// Only ever the managed line of that address. The user may have the same host elsewhere.
+			Self::AddHost(address, host) => {
+				hosts.delete(DeletionType::Entry(*address, host))?;
+			}
+
+			Self::DeleteHost(address, host) => {
+				let exists = hosts.items.iter().any(|v| v.managed && v.address == *address && v.has_name(host));
+
+				if !is_wildcard(host) && !exists {
+					hosts.add(*address, host.clone())?;
+				}
+			}
+
+			Self::AddBridge(bridge) => {
+				if backend.contains(bridge.listen_to) {
+					backend.delete_bridge(bridge)?;
+				}
+			}
+
+			Self::DeleteBridge(bridge) => {
+				if !backend.contains(bridge.listen_to) {
+					backend.add_bridge(bridge.clone())?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	fn to_line(&self) -> String {
+		match self {
+			Self::AddHost(address, host) => format!("add-host {} {}", address, host),
+			Self::DeleteHost(address, host) => format!("delete-host {} {}", address, host),
+			Self::AddBridge(bridge) => format!("add-bridge {} {}", bridge.listen_to, bridge.connect_to),
+			Self::DeleteBridge(bridge) => format!("delete-bridge {} {}", bridge.listen_to, bridge.connect_to)
+		}
+	}
+
+	fn from_line(line: &str) -> Option<Self> {
+		let mut split = line.split_ascii_whitespace();
+
+		let kind = split.next()?;
+		let (first, second) = (split.next()?, split.next()?);
+
+		let bridge = || -> Option<Bridge> {
+			Some(Bridge {
+				listen_to: first.parse().ok()?,
+				connect_to: second.parse().ok()?
+			})
+		};
+
+		Some(match kind {
+			"add-host" => Self::AddHost(first.parse().ok()?, second.to_string()),
+			"delete-host" => Self::DeleteHost(first.parse().ok()?, second.to_string()),
+			"add-bridge" => Self::AddBridge(bridge()?),
+			"delete-bridge" => Self::DeleteBridge(bridge()?),
+			_ => return None
+		})
+	}
+}
+
+
+/// Steps of the current transaction. Each step is written before it's applied and marked once it completes.
+///
+/// If the program is interrupted the journal is left behind so the steps can be undone on the next run.
+/// Once every step is applied the journal is marked committed before the state is saved, so a committed
+/// transaction is never undone.
+#[derive(Debug, Default)]
+pub struct Journal {
+	/// Where the journal is persisted. Kept in memory only if None.
+	path: Option<PathBuf>,
+	/// Steps and whether they were completed.
+	steps: Vec<(Step, bool)>,
+	/// Whether every step was applied and the transaction only has to be cleaned up.
+	committed: bool
+}
+
+impl Journal {
+	/// Default location of the journal inside the users' config directory.
+	pub fn default_path() -> Result<PathBuf> {
+		let dir = dirs::config_dir().context("Unable to find config directory")?;
+
+		Ok(dir.join("localhosting").join("journal"))
+	}
+
+	pub fn load(path: PathBuf) -> Result<Self> {
+		let contents = if path.exists() { fs::read_to_string(&path)? } else { String::new() };
+
+		let steps = contents.lines()
+			// Each line is "<pending/done> <step>"
+			.filter_map(|line| {
+				let (state, step) = line.split_once(' ')?;
+				Some((Step::from_line(step)?, state == "done"))
+			})
+			.collect();
+
+		Ok(Self {
+			path: Some(path),
+			steps,
+			// Followed by "commit" once every step was applied.
+			committed: contents.lines().any(|v| v == COMMIT_LINE)
+		})
+	}
+
+	/// Whether a previous transaction never finished.
+	pub fn is_interrupted(&self) -> bool {
+		!self.steps.is_empty()
+	}
+
+	pub fn steps(&self) -> impl Iterator<Item = &Step> {
+		self.steps.iter().map(|(v, _)| v)
+	}
+
+	/// Undoes every step of an interrupted transaction. A committed one is only removed.
+	///
+	/// Pending steps are undone as well since they may have been partially applied.
+	pub fn recover(&mut self, backend: &mut dyn ForwardingBackend, hosts: &mut HostFile) -> Result<()> {
+		if self.committed {
+			return self.clear();
+		}
+
+		while let Some((step, _)) = self.steps.last().cloned() {
+			step.undo(backend, hosts)?;
+
+			self.steps.pop();
+			self.save()?;
+		}
+
+		Ok(())
+	}
+
+	fn push(&mut self, step: Step) -> Result<()> {
+		self.steps.push((step, false));
+		self.save()
+	}
+
+	fn complete_last(&mut self) -> Result<()> {
+		if let Some(last) = self.steps.last_mut() {
+			last.1 = true;
+		}
+
+		self.save()
+	}
+
+	/// Written before the state is saved. From then on the steps are kept even if the program is interrupted.
+	fn mark_committed(&mut self) -> Result<()> {
+		self.committed = true;
+		self.save()
+	}
+
+	fn clear(&mut self) -> Result<()> {
+		self.steps.clear();
+		self.committed = false;
+		self.save()
+	}
+
+	fn save(&self) -> Result<()> {
+		let path = match &self.path {
+			Some(v) => v,
+			None => return Ok(())
+		};
+
+		if self.steps.is_empty() {
+			if path.exists() {
+				fs::remove_file(path)?;
+			}
+
+			return Ok(());
+		}
+
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+
+		let mut contents = self.steps.iter()
+			.map(|(step, done)| format!("{} {}\n", if *done { "done" } else { "pending" }, step.to_line()))
+			.collect::<String>();
+
+		if self.committed {
+			contents += &format!("{}\n", COMMIT_LINE);
+		}
+
+		write_atomic(path, &contents)
+	}
+}
+
+
+/// Applies changes to both the hosts file and forwarding backend, undoing them all if any fail.
+///
+/// The state is only updated once everything was applied.
+pub struct Transaction<'a> {
+	backend: &'a mut dyn ForwardingBackend,
+	hosts: &'a mut HostFile,
+	state: &'a mut State,
+	journal: &'a mut Journal,
+	/// Project which owns the hosts put on a bridge by this transaction.
+	owner: Option<String>,
+	/// Listening connections and hosts which no longer belong to their project.
+	disowned: Vec<(Connection, String)>
+}
+
+impl<'a> Transaction<'a> {
+	/// Runs `f` inside a transaction. It's committed if `f` succeeds, otherwise everything it did is undone.
+	pub fn run<T, F>(
+		backend: &'a mut dyn ForwardingBackend,
+		hosts: &'a mut HostFile,
+		state: &'a mut State,
+		journal: &'a mut Journal,
+		f: F
+	) -> Result<T>
+	where
+		F: FnOnce(&mut Transaction<'a>) -> Result<T>
+	{
+		if journal.is_interrupted() {
+			bail!("A previous run was interrupted. Its' changes have to be recovered first.");
+		}
+
+		let mut transaction = Transaction {
+			backend,
+			hosts,
+			state,
+			journal,
+			owner: None,
+			disowned: Vec::new()
+		};
+
+		match f(&mut transaction).and_then(|v| transaction.commit().map(|_| v)) {
+			Ok(v) => {
+				transaction.journal.clear()?;
+				Ok(v)
+			}
+
+			// The original failure is what matters. The journal keeps what couldn't be undone for the next run.
+			Err(e) => match transaction.journal.recover(&mut *transaction.backend, &mut *transaction.hosts) {
+				Ok(_) => Err(e),
+				Err(recovery) => Err(e.context(format!("Unable to undo the changes: {:#}", recovery)))
+			}
+		}
+	}
+
+	pub fn backend(&self) -> &dyn ForwardingBackend {
+		self.backend
+	}
+
+	pub fn hosts(&self) -> &HostFile {
+		self.hosts
+	}
+
+	/// Everything changed so far.
+	pub fn changes(&self) -> Vec<Change> {
+		self.journal.steps().map(Change::from).collect()
+	}
+
+	/// State from before the transaction started.
+	pub fn state(&self) -> &State {
+		self.state
+	}
+
+	/// State with every change made so far.
+	pub fn current_state(&self) -> State {
+		let mut state = self.state.detached();
+
+		for step in self.journal.steps() {
+			state.apply(step);
+		}
+
+		state
+	}
+
+	/// Hosts this transaction puts on a bridge belong to the project once it's committed.
+	pub fn claim_for(&mut self, project_id: &str) {
+		self.owner = Some(project_id.to_string());
+	}
+
+	/// The hosts no longer belong to their project once committed, even if they stay on the bridge.
+	pub fn disown(&mut self, released: &[(Connection, String)]) {
+		self.disowned.extend_from_slice(released);
+	}
+
+	/// Returns The Bridge and bool specifying if it's new.
+	///
+	/// A stored mapping whose bridge went missing gets its' bridge recreated with the same address.
+	/// New bridges get their address allocated for `host` and listen on `port`.
+	pub fn add_or_retreive(&mut self, connect_to: Connection, host: &str, port: u16) -> Result<(Bridge, bool)> {
+		let state = self.current_state();
+		let listen_to = state.find_by_connection(connect_to).map(|v| v.listen_to);
+
+		if let Some(bridge) = self.backend.get_by_connection(listen_to.unwrap_or(connect_to)) {
+			return Ok((bridge.clone(), false));
+		}
+
+		let listen_to = match listen_to {
+			Some(v) => v,
+			None => state.allocate(host, port, &*self.backend)?
+		};
+
+		let bridge = Bridge {
+			listen_to,
+			connect_to
+		};
+
+		self.journal.push(Step::AddBridge(bridge.clone()))?;
+		self.backend.add_bridge(bridge.clone())?;
+		self.journal.complete_last()?;
+
+		Ok((bridge, true))
+	}
+
+	pub fn delete_bridge(&mut self, connection: Connection) -> Result<Option<Bridge>> {
+		let bridge = match self.backend.get_by_connection(connection) {
+			Some(v) => v.clone(),
+			None => return Ok(None)
+		};
+
+		self.journal.push(Step::DeleteBridge(bridge.clone()))?;
+		self.backend.delete_bridge(&bridge)?;
+		self.journal.complete_last()?;
+
+		Ok(Some(bridge))
+	}
+
+	/// Wildcards are only recorded in the state.
+	pub fn add_host(&mut self, address: IpAddr, host: String) -> Result<()> {
+		self.journal.push(Step::AddHost(address, host.clone()))?;
+
+		if !is_wildcard(&host) {
+			self.hosts.add(address, host)?;
+		}
+
+		self.journal.complete_last()
+	}
+
+	pub fn delete_hosts(&mut self, value: DeletionType) -> Result<Vec<HostItem>> {
+		let wildcards = self.wildcards(&value);
+
+		let mut matching = self.hosts.matching(&value);
+		matching.extend(wildcards.iter().cloned());
+
+		if matching.is_empty() {
+			return Ok(matching);
+		}
+
+		// Every name is recorded so they can all be added back.
+		for item in &matching {
+			for name in item.names() {
+				self.journal.push(Step::DeleteHost(item.address, name.to_string()))?;
+			}
+		}
+
+		let mut deleted = self.hosts.delete(value)?;
+		deleted.extend(wildcards);
+
+		for (_, done) in &mut self.journal.steps {
+			*done = true;
+		}
+
+		self.journal.save()?;
+
+		Ok(deleted)
+	}
+
+	/// Stored wildcards matching the deletion. They aren't in the hosts file.
+	fn wildcards(&self, value: &DeletionType) -> Vec<HostItem> {
+		let mut matching = Vec::<HostItem>::new();
+
+		for mapping in &self.state.mappings {
+			let address = mapping.listen_to.address;
+
+			for host in mapping.hosts.iter().filter(|v| is_wildcard(v)) {
+				let matches = match value {
+					DeletionType::Address(v) => *v == address,
+					DeletionType::Host(v) => v.eq_ignore_ascii_case(host),
+					DeletionType::Entry(v, h) => *v == address && h.eq_ignore_ascii_case(host)
+				};
+
+				if matches && !matching.iter().any(|v| v.address == address && &v.host == host) {
+					matching.push(HostItem {
+						address,
+						host: host.clone(),
+						aliases: Vec::new(),
+						managed: true
+					});
+				}
+			}
+		}
+
+		matching
+	}
+
+	/// Records every step and the ownership of the hosts in the state.
+	fn commit(&mut self) -> Result<()> {
+		if self.journal.steps.is_empty() && self.disowned.is_empty() {
+			return Ok(());
+		}
+
+		if !self.journal.steps.is_empty() {
+			self.journal.mark_committed()?;
+		}
+
+		let before = self.state.pairs();
+
+		for step in self.journal.steps() {
+			self.state.apply(step);
+		}
+
+		for mapping in &mut self.state.mappings {
+			let listen_to = mapping.listen_to;
+			mapping.owners.retain(|host, _| !self.disowned.iter().any(|(l, h)| *l == listen_to && h == host));
+
+			// Hosts which were already on the bridge keep their owner.
+			if let Some(owner) = &self.owner {
+				for host in mapping.hosts.iter().filter(|v| !before.contains(&(listen_to, v.to_string()))) {
+					mapping.owners.insert(host.clone(), owner.clone());
+				}
+			}
+		}
+
+		self.state.save()
+	}
+}
@@ -0,0 +1,214 @@
+use std::{
+	collections::BTreeMap,
+	fs,
+	path::{Component, Path, PathBuf}
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+
+/// How the test server answers a host.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
+pub enum Mock {
+	/// Replies with the host name.
+	#[default]
+	Text,
+	/// Replies with the request as JSON.
+	Echo,
+	/// Serves the files inside the directory.
+	Static {
+		dir: PathBuf
+	},
+	/// Replies with the first matching route of the routes file.
+	Routes {
+		file: PathBuf,
+		#[serde(skip)]
+		routes: Vec<MockRoute>
+	}
+}
+
+impl Mock {
+	/// Parses "text", "echo", "static:<dir>" or "routes:<file>". The routes file is loaded.
+	pub fn parse(value: &str) -> Result<Self> {
+		let (mode, path) = value.split_once(':').unwrap_or((value, ""));
+
+		Ok(match (mode, path) {
+			("text", "") => Self::Text,
+			("echo", "") => Self::Echo,
+			("static", dir) if !dir.is_empty() => {
+				if !Path::new(dir).is_dir() {
+					return Err(Error::NotFound(dir.to_string()).into());
+				}
+
+				Self::Static { dir: PathBuf::from(dir) }
+			}
+			("routes", file) if !file.is_empty() => Self::Routes {
+				file: PathBuf::from(file),
+				routes: MockRoute::load(Path::new(file))?
+			},
+			_ => return Err(Error::parse(value, "Expected text, echo, static:<dir> or routes:<file>").into())
+		})
+	}
+
+	pub fn respond(&self, request: &MockRequest) -> MockResponse {
+		match self {
+			Self::Text => MockResponse::text(200, format!("Viewing Host {:?}", request.host)),
+
+			Self::Echo => MockResponse {
+				status: 200,
+				headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+				body: serde_json::to_vec_pretty(request).unwrap_or_default()
+			},
+
+			Self::Static { dir } => serve_file(dir, &request.path),
+
+			Self::Routes { routes, .. } => routes.iter()
+				.find(|v| v.matches(&request.method, &request.path))
+				.map(|v| MockResponse {
+					status: v.status,
+					headers: v.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+					body: v.body.clone().into_bytes()
+				})
+				.unwrap_or_else(|| MockResponse::text(404, format!("No route for {} {}", request.method, request.path)))
+		}
+	}
+}
+
+
+/// Canned response of a routes file.
+///
+/// ```toml
+/// [[routes]]
+/// method = "POST" # Optional. Any method if missing.
+/// path = "/api/*" # Ending with "*" matches everything starting with the rest.
+/// status = 201 # Optional. Defaults to 200.
+/// headers = { Content-Type = "application/json" }
+/// body = '{ "id": 1 }'
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MockRoute {
+	#[serde(default)]
+	pub method: Option<String>,
+	pub path: String,
+	#[serde(default = "default_status")]
+	pub status: u16,
+	#[serde(default)]
+	pub headers: BTreeMap<String, String>,
+	#[serde(default)]
+	pub body: String
+}
+
+impl MockRoute {
+	pub fn load(path: &Path) -> Result<Vec<Self>> {
+		let contents = fs::read_to_string(path)
+			.map_err(|_| Error::NotFound(path.display().to_string()))?;
+
+		Self::parse(&contents).map_err(|e| Error::parse(&path.display().to_string(), e).into())
+	}
+
+	pub fn parse(value: &str) -> Result<Vec<Self>, toml::de::Error> {
+		#[derive(Deserialize)]
+		struct RoutesFile {
+			#[serde(default)]
+			routes: Vec<MockRoute>
+		}
+
+		Ok(toml::from_str::<RoutesFile>(value)?.routes)
+	}
+
+	pub fn matches(&self, method: &str, path: &str) -> bool {
+		let method_matches = self.method.as_deref()
+			.map(|v| v.eq_ignore_ascii_case(method))
+			.unwrap_or(true);
+
+		let path_matches = match self.path.strip_suffix('*') {
+			Some(prefix) => path.starts_with(prefix),
+			None => self.path == path
+		};
+
+		method_matches && path_matches
+	}
+}
+
+fn default_status() -> u16 {
+	200
+}
+
+
+/// Request as the mock sees it. Serialized for echo.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MockRequest {
+	pub method: String,
+	pub host: String,
+	pub path: String,
+	#[serde(skip_serializing_if = "String::is_empty")]
+	pub query: String,
+	/// Values of repeated headers are joined with ", ".
+	pub headers: BTreeMap<String, String>,
+	/// Invalid UTF-8 is replaced.
+	pub body: String
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockResponse {
+	pub status: u16,
+	pub headers: Vec<(String, String)>,
+	pub body: Vec<u8>
+}
+
+impl MockResponse {
+	fn text(status: u16, body: String) -> Self {
+		Self {
+			status,
+			headers: vec![("Content-Type".to_string(), "text/plain; charset=utf-8".to_string())],
+			body: body.into_bytes()
+		}
+	}
+}
+
+
+/// Serves the file at the path. Directories serve their "index.html".
+fn serve_file(dir: &Path, path: &str) -> MockResponse {
+	let relative = Path::new(path.trim_start_matches('/'));
+
+	// Never leave the directory.
+	if relative.components().any(|v| !matches!(v, Component::Normal(_))) {
+		return MockResponse::text(404, format!("Not found {}", path));
+	}
+
+	let mut file = dir.join(relative);
+
+	if file.is_dir() {
+		file = file.join("index.html");
+	}
+
+	match fs::read(&file) {
+		Ok(body) => MockResponse {
+			status: 200,
+			headers: vec![("Content-Type".to_string(), content_type(&file).to_string())],
+			body
+		},
+		Err(_) => MockResponse::text(404, format!("Not found {}", path))
+	}
+}
+
+fn content_type(path: &Path) -> &'static str {
+	match path.extension().and_then(|v| v.to_str()).unwrap_or_default() {
+		"html" | "htm" => "text/html; charset=utf-8",
+		"css" => "text/css",
+		"js" | "mjs" => "text/javascript",
+		"json" => "application/json",
+		"txt" => "text/plain; charset=utf-8",
+		"svg" => "image/svg+xml",
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"ico" => "image/x-icon",
+		"wasm" => "application/wasm",
+		_ => "application/octet-stream"
+	}
+}
@@ -1,41 +1,112 @@
+mod allocator;
+mod backup;
+mod cert;
+mod dns;
+mod document;
+mod error;
+mod forwarding;
 mod hosts;
+mod journal;
+mod mock;
+mod nat;
 mod netsh;
+mod output;
+mod plan;
+mod port;
+mod project;
+mod state;
+mod tls;
+mod userspace;
 
 
-use std::{fmt::{self, Display}, net::Ipv4Addr, str::FromStr};
+use std::{fmt::{self, Display}, net::{IpAddr, SocketAddr}, str::FromStr};
 
-pub use hosts::{HostFile, HostItem, DeletionType, has_write_permissions};
-pub use netsh::{Bridge, NetSH, ProxyBridge};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub use allocator::{Allocator, Strategy, Subnet, DEFAULT_LISTEN_PORT};
+pub use backup::{Backups, write_atomic, write_private, DEFAULT_BACKUP_COUNT};
+pub use cert::{CertStore, Leaf, LEAF_VALIDITY_DAYS, RENEW_BEFORE_DAYS, ROOT_VALIDITY_DAYS};
+pub use error::{Error, ELEVATION_HINT};
+pub use dns::{DnsServer, RecordType, Zone, query, parse_response, DEFAULT_DNS_LISTEN, RCODE_NO_ERROR, RCODE_FORMAT_ERROR, RCODE_SERVER_FAILURE, RCODE_REFUSED};
+pub use document::{HostsDocument, Line, LineKind, Entry, BLOCK_BEGIN, BLOCK_END};
+pub use forwarding::{Bridge, ForwardingBackend, MemoryBackend};
+pub use hosts::{HostFile, HostItem, HostsStore, FileStore, MemoryStore, DeletionType, has_write_permissions, is_wildcard, hosts_file_path, system_hosts_path};
+pub use journal::{Journal, Step, Transaction};
+pub use mock::{Mock, MockRequest, MockResponse, MockRoute};
+pub use nat::{NatRules, NatTool};
+pub use netsh::{NetSH, ProxyBridge};
+pub use output::{Change, Format, Report, table};
+pub use plan::{DryRun, Plan, diff_lines};
+pub use port::{PortOwner, PortStatus};
+pub use project::{Project, ProjectEntry, PROJECT_FILE_NAME};
+pub use state::{Mapping, State, Target};
+pub use tls::CertResolver;
+pub use userspace::UserspaceProxy;
 
 
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Connection {
-	pub address: Ipv4Addr,
+	pub address: IpAddr,
 	pub port: u16
 }
 
-impl From<(Ipv4Addr, u16)> for Connection {
-    fn from(value: (Ipv4Addr, u16)) -> Self {
+impl<A: Into<IpAddr>> From<(A, u16)> for Connection {
+    fn from(value: (A, u16)) -> Self {
         Self {
-			address: value.0,
+			address: value.0.into(),
 			port: value.1
 		}
     }
 }
 
+impl From<SocketAddr> for Connection {
+	fn from(value: SocketAddr) -> Self {
+		Self::from((value.ip(), value.port()))
+	}
+}
+
+impl From<Connection> for SocketAddr {
+	fn from(value: Connection) -> Self {
+		SocketAddr::new(value.address, value.port)
+	}
+}
+
 
 impl FromStr for Connection {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-		let (addr, port) = s.split_once(':').expect("Expected a port");
-        Ok(Self { address: addr.parse()?, port: port.parse()? })
+		// IPv6 addresses have to be wrapped in brackets. ex: [::1]:8080
+		if s.starts_with('[') {
+			return Ok(s.parse::<SocketAddr>().map_err(|e| Error::parse(s, e))?.into());
+		}
+
+		let (addr, port) = s.split_once(':').ok_or_else(|| Error::parse(s, "Expected a port"))?;
+
+        Ok(Self {
+			address: addr.parse().map_err(|e| Error::parse(s, e))?,
+			port: port.parse().map_err(|e| Error::parse(s, e))?
+		})
     }
 }
 
 impl Display for Connection {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		write!(f, "{}:{}", self.address, self.port)
+		write!(f, "{}", SocketAddr::from(*self))
+	}
+}
+
+/// Stored as "127.0.0.1:8080"
+impl Serialize for Connection {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
 	}
-}
\ No newline at end of file
+}
+
+impl<'de> Deserialize<'de> for Connection {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let value = String::deserialize(deserializer)?;
+		value.parse().map_err(serde::de::Error::custom)
+	}
+}
@@ -0,0 +1,324 @@
+use std::{
+	io::Write,
+	path::PathBuf,
+	process::{Command, Output, Stdio}
+};
+
+use anyhow::{Result, bail};
+
+use crate::{Bridge, Connection, Error, ForwardingBackend};
+
+
+/// nftables table owned by localhosting.
+pub const NFT_TABLE_NAME: &str = "localhosting";
+/// iptables chain (inside the "nat" table) owned by localhosting.
+pub const IPTABLES_CHAIN_NAME: &str = "LOCALHOSTING";
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatTool {
+	Nftables,
+	Iptables
+}
+
+impl NatTool {
+	pub fn program(self) -> &'static str {
+		match self {
+			Self::Nftables => "nft",
+			Self::Iptables => "iptables"
+		}
+	}
+}
+
+
+/// Linux forwarding using DNAT rules inside a table/chain which only localhosting writes to.
+///
+/// Every change regenerates the whole rule set so the table/chain always matches `bridges`.
+pub struct NatRules {
+	tool: NatTool,
+	/// Executable called for the tool. Overridable for tests.
+	program: PathBuf,
+	bridges: Vec<Bridge>,
+	/// Why the rules couldn't be listed. Read-only use sees no bridges, changing them fails with it.
+	unlisted: Option<Output>
+}
+
+impl NatRules {
+	/// Uses nftables if it's installed, otherwise falls back to iptables.
+	pub fn detect() -> Result<Self> {
+		for tool in [NatTool::Nftables, NatTool::Iptables] {
+			let found = Command::new(tool.program())
+				.arg("--version")
+				.output()
+				.map(|v| v.status.success())
+				.unwrap_or_default();
+
+			if found {
+				return Self::create(tool, tool.program());
+			}
+		}
+
+		Err(Error::NotFound("nft or iptables".to_string()).into())
+	}
+
+	pub fn create(tool: NatTool, program: impl Into<PathBuf>) -> Result<Self> {
+		let program = program.into();
+
+		let output = match tool {
+			NatTool::Nftables => Command::new(&program)
+				.args(["list", "table", "ip", NFT_TABLE_NAME])
+				.output()?,
+
+			NatTool::Iptables => Command::new(&program)
+				.args(["-t", "nat", "-S", IPTABLES_CHAIN_NAME])
+				.output()?
+		};
+
+		let mut unlisted = None;
+
+		let bridges = if output.status.success() {
+			let value = String::from_utf8(output.stdout)?;
+
+			value.lines()
+				.filter_map(|line| match tool {
+					NatTool::Nftables => parse_nft_line(line),
+					NatTool::Iptables => parse_iptables_line(line)
+				})
+				.collect()
+		} else {
+			let stderr = String::from_utf8_lossy(&output.stderr);
+
+			// Nothing has been added yet.
+			if stderr.contains("No such file or directory") || stderr.contains("No chain/target/match") {
+				Vec::new()
+			// Listing needs root as well. Commands which only read shouldn't.
+			} else if stderr.contains("Operation not permitted") || stderr.contains("Permission denied") {
+				unlisted = Some(output);
+				Vec::new()
+			} else {
+				return Err(Error::backend(tool.program(), "LIST", &output).into());
+			}
+		};
+
+		Ok(Self {
+			tool,
+			program,
+			bridges,
+			unlisted
+		})
+	}
+
+	pub fn tool(&self) -> NatTool {
+		self.tool
+	}
+
+	/// The full rule set which gets applied for the current bridges.
+	///
+	/// For nftables this is a script for `nft -f -`, for iptables one argument list per line.
+	pub fn ruleset(&self) -> String {
+		self.ruleset_for(&self.bridges)
+	}
+
+	fn ruleset_for(&self, bridges: &[Bridge]) -> String {
+		match self.tool {
+			NatTool::Nftables => {
+				let mut value = format!(
+					"add table ip {0}\nflush table ip {0}\ntable ip {0} {{\n\tchain output {{\n\t\ttype nat hook output priority -100; policy accept;\n",
+					NFT_TABLE_NAME
+				);
+
+				for bridge in bridges {
+					value += &format!(
+						"\t\tip daddr {} tcp dport {} dnat to {}\n",
+						bridge.listen_to.address,
+						bridge.listen_to.port,
+						bridge.connect_to
+					);
+				}
+
+				value += "\t}\n}\n";
+
+				value
+			}
+
+			NatTool::Iptables => {
+				let mut lines = vec![format!("-t nat -F {}", IPTABLES_CHAIN_NAME)];
+
+				for bridge in bridges {
+					lines.push(format!(
+						"-t nat -A {} -d {}/32 -p tcp --dport {} -j DNAT --to-destination {}",
+						IPTABLES_CHAIN_NAME,
+						bridge.listen_to.address,
+						bridge.listen_to.port,
+						bridge.connect_to
+					));
+				}
+
+				lines.join("\n")
+			}
+		}
+	}
+
+	fn apply(&self) -> Result<()> {
+		// The rule set is replaced as a whole, so unknown rules would be lost.
+		if let Some(output) = &self.unlisted {
+			return Err(Error::backend(self.tool.program(), "LIST", output).into());
+		}
+
+		let ruleset = self.ruleset();
+
+		match self.tool {
+			NatTool::Nftables => {
+				let mut child = Command::new(&self.program)
+					.args(["-f", "-"])
+					.stdin(Stdio::piped())
+					.stdout(Stdio::piped())
+					.stderr(Stdio::piped())
+					.spawn()?;
+
+				child.stdin.take().unwrap().write_all(ruleset.as_bytes())?;
+
+				let output = child.wait_with_output()?;
+
+				if !output.status.success() {
+					return Err(Error::backend("nft", "APPLY", &output).into());
+				}
+			}
+
+			NatTool::Iptables => {
+				// Create our chain and jump to it from OUTPUT. Creating fails if it already exists.
+				self.iptables(&format!("-t nat -N {}", IPTABLES_CHAIN_NAME)).ok();
+
+				if self.iptables(&format!("-t nat -C OUTPUT -j {}", IPTABLES_CHAIN_NAME)).is_err() {
+					self.iptables(&format!("-t nat -A OUTPUT -j {}", IPTABLES_CHAIN_NAME))?;
+				}
+
+				for line in ruleset.lines() {
+					self.iptables(line)?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Commands `apply` runs for the bridges. The script is passed to nft on stdin.
+	fn plan(&self, bridges: &[Bridge]) -> Vec<String> {
+		let program = self.program.display();
+		let ruleset = self.ruleset_for(bridges);
+
+		match self.tool {
+			NatTool::Nftables => vec![format!("{} -f - <<EOF\n{}EOF", program, ruleset)],
+
+			NatTool::Iptables => [
+				format!("-t nat -N {}", IPTABLES_CHAIN_NAME),
+				format!("-t nat -C OUTPUT -j {0} || {1} -t nat -A OUTPUT -j {0}", IPTABLES_CHAIN_NAME, program)
+			]
+				.iter()
+				.map(String::as_str)
+				.chain(ruleset.lines())
+				.map(|v| format!("{} {}", program, v))
+				.collect()
+		}
+	}
+
+	fn iptables(&self, args: &str) -> Result<()> {
+		let output = Command::new(&self.program)
+			.args(args.split_ascii_whitespace())
+			.output()?;
+
+		if !output.status.success() {
+			return Err(Error::backend("iptables", "APPLY", &output).into());
+		}
+
+		Ok(())
+	}
+}
+
+impl ForwardingBackend for NatRules {
+	fn bridges(&self) -> &[Bridge] {
+		&self.bridges
+	}
+
+	fn add_bridge(&mut self, bridge: Bridge) -> Result<()> {
+		// NAT can't translate between address families.
+		if bridge.listen_to.address.is_ipv4() != bridge.connect_to.address.is_ipv4() {
+			bail!("Unable to forward {} to {} using NAT rules. Use the userspace backend instead.", bridge.listen_to, bridge.connect_to);
+		}
+
+		self.bridges.push(bridge);
+
+		if let Err(e) = self.apply() {
+			self.bridges.pop();
+			return Err(e);
+		}
+
+		Ok(())
+	}
+
+	fn delete_bridge(&mut self, bridge: &Bridge) -> Result<()> {
+		let previous = self.bridges.clone();
+
+		self.bridges.retain(|v| v.listen_to != bridge.listen_to);
+
+		if let Err(e) = self.apply() {
+			self.bridges = previous;
+			return Err(e);
+		}
+
+		Ok(())
+	}
+
+	fn plan_add_bridge(&self, current: &[Bridge], bridge: &Bridge) -> Vec<String> {
+		let mut bridges = current.to_vec();
+		bridges.push(bridge.clone());
+
+		self.plan(&bridges)
+	}
+
+	fn plan_delete_bridge(&self, current: &[Bridge], bridge: &Bridge) -> Vec<String> {
+		let bridges = current.iter()
+			.filter(|v| v.listen_to != bridge.listen_to)
+			.cloned()
+			.collect::<Vec<_>>();
+
+		self.plan(&bridges)
+	}
+}
+
+
+
+/// Parses "ip daddr 127.1.2.3 tcp dport 80 dnat to 127.0.0.1:8080"
+fn parse_nft_line(line: &str) -> Option<Bridge> {
+	let mut split = line.split_ascii_whitespace();
+
+	let address = split.find(|v| *v == "daddr").and_then(|_| split.next())?;
+	let port = split.find(|v| *v == "dport").and_then(|_| split.next())?;
+	let connect_to = split.find(|v| *v == "to").and_then(|_| split.next())?;
+
+	Some(Bridge {
+		listen_to: Connection {
+			address: address.parse().ok()?,
+			port: port.parse().ok()?
+		},
+		connect_to: connect_to.parse().ok()?
+	})
+}
+
+/// Parses "-A LOCALHOSTING -d 127.1.2.3/32 -p tcp -m tcp --dport 80 -j DNAT --to-destination 127.0.0.1:8080"
+fn parse_iptables_line(line: &str) -> Option<Bridge> {
+	let mut split = line.split_ascii_whitespace();
+
+	let address = split.find(|v| *v == "-d").and_then(|_| split.next())?;
+	let address = address.trim_end_matches("/32");
+	let port = split.find(|v| *v == "--dport").and_then(|_| split.next())?;
+	let connect_to = split.find(|v| *v == "--to-destination").and_then(|_| split.next())?;
+
+	Some(Bridge {
+		listen_to: Connection {
+			address: address.parse().ok()?,
+			port: port.parse().ok()?
+		},
+		connect_to: connect_to.parse().ok()?
+	})
+}
@@ -1,204 +1,157 @@
-use std::{
-	net::Ipv4Addr,
-	process::Command
-};
+use std::process::Command;
 
 use anyhow::Result;
 
-use crate::Connection;
+use crate::{Bridge, Connection, Error, ForwardingBackend};
 
 
-#[derive(Default)]
 pub struct NetSH {
-	/// Check to see if we should call the "netsh" program or not. Used for tests.
-	pub call_to_program: bool,
-	pub bridges: Vec<Bridge>
+	bridges: Vec<Bridge>
 }
 
 impl NetSH {
-	pub fn create(proxy: ProxyBridge) -> Result<Self> {
-		let output = Command::new("netsh")
-			.args(&["interface", "portproxy", "show", proxy.into_str()])
-			.output()?;
+	pub fn create() -> Result<Self> {
+		let mut bridges = Vec::new();
 
-		if !output.status.success() {
-			panic!("[netsh][LIST]: {}", output.status);
-		}
+		for proxy in ProxyBridge::ALL {
+			let output = Command::new("netsh")
+				.args(["interface", "portproxy", "show", proxy.into_str()])
+				.output()?;
 
-		let value = String::from_utf8(output.stdout)?;
-
-		let bridges = value.lines()
-			// Skip until "-"
-			.skip_while(|v| !v.starts_with('-'))
-			// Skip "-"
-			.skip(1)
-			// Parse lines.
-			.filter_map(|line| {
-				let mut split = line.split_ascii_whitespace();
-
-				Some(Bridge {
-					listen_to: Connection {
-						address: split.next()?.parse().ok()?,
-						port: split.next()?.parse().ok()?
-					},
-
-					connect_to: Connection {
-						address: split.next()?.parse().ok()?,
-						port: split.next()?.parse().ok()?
-					}
-				})
-			})
-			.collect::<Vec<_>>();
+			if !output.status.success() {
+				return Err(Error::backend("netsh", "LIST", &output).into());
+			}
+
+			let value = String::from_utf8(output.stdout)?;
+
+			let found = value.lines()
+				// Skip until "-"
+				.skip_while(|v| !v.starts_with('-'))
+				// Skip "-"
+				.skip(1)
+				// Parse lines.
+				.filter_map(|line| {
+					let mut split = line.split_ascii_whitespace();
+
+					Some(Bridge {
+						listen_to: Connection {
+							address: split.next()?.parse().ok()?,
+							port: split.next()?.parse().ok()?
+						},
+
+						connect_to: Connection {
+							address: split.next()?.parse().ok()?,
+							port: split.next()?.parse().ok()?
+						}
+					})
+				});
+
+			bridges.extend(found);
+		}
 
 		Ok(Self {
-			call_to_program: true,
 			bridges
 		})
 	}
+}
 
-	pub fn contains(&self, value: Connection) -> bool {
-		self.bridges.iter().any(|v| v.connect_to == value || v.listen_to == value)
-	}
-
-	pub fn get_by_connection(&self, value: Connection) -> Option<&Bridge> {
-		self.bridges.iter().find(|v| v.connect_to == value || v.listen_to == value)
+impl ForwardingBackend for NetSH {
+	fn bridges(&self) -> &[Bridge] {
+		&self.bridges
 	}
 
-	pub fn delete(&mut self, connect_to: Connection, proxy: ProxyBridge) -> Result<Option<Bridge>> {
-		if let Some(index) = self.bridges.iter().position(|v| v.listen_to == connect_to || v.connect_to == connect_to) {
-			let bridge = self.bridges.remove(index);
-
-			if self.call_to_program {
-				bridge.delete(proxy)?;
-			} else {
-				println!("[netsh][test]: Deleting bridge {}.", bridge.listen_to);
-			}
+	fn add_bridge(&mut self, bridge: Bridge) -> Result<()> {
+		let output = Command::new("netsh")
+			.args(add_args(&bridge))
+			.output()?;
 
-			Ok(Some(bridge))
-		} else {
-			Ok(None)
+		if !output.status.success() {
+			return Err(Error::backend("netsh", "ADD", &output).into());
 		}
-	}
 
-	/// Returns The Bridge and bool specifying if it's new.
-	pub fn add_or_retreive(&mut self, connect_to: Connection, proxy: ProxyBridge) -> Result<(Bridge, bool)> {
-		if let Some(c) = self.get_by_connection(connect_to) {
-			Ok((c.clone(), false))
-		} else {
-			// Find unused listening address.
-			let listen_to = loop {
-				let oct = gen_octets();
-
-				// Since people normally use 127.0.0.* I don't want to infringe on it.
-				if oct.0 == 0 && oct.1 == 0 {
-					continue;
-				}
-
-				let conn = Connection::from((Ipv4Addr::new(127, oct.0, oct.1, oct.2), 80));
-
-				if !self.contains(conn) {
-					break conn;
-				}
-			};
-
-			// Find connecting address & port.
-			if self.contains(connect_to) {
-				panic!("Bridge already exists for {}.", connect_to);
-			}
+		self.bridges.push(bridge);
 
-			if self.call_to_program {
-				// netsh interface portproxy add v4tov4 listenport=80 listenaddress=127.*.*.* connectport=**** connectaddress=127.0.0.1
-				let output = Command::new("netsh")
-					.args(&[
-						"interface",
-						"portproxy",
-						"add",
-						proxy.into_str(),
-						&format!("listenaddress={}", listen_to.address),
-						&format!("listenport={}", listen_to.port),
-						&format!("connectaddress={}", connect_to.address),
-						&format!("connectport={}", connect_to.port),
-					])
-					.output()?;
-
-				if !output.status.success() {
-					panic!("[netsh][ADD]: {}", output.status);
-				}
-			} else {
-				println!("[netsh][test]: Adding portproxy to self.");
-			}
+		Ok(())
+	}
 
-			self.bridges.push(Bridge {
-				listen_to,
-				connect_to
-			});
+	fn delete_bridge(&mut self, bridge: &Bridge) -> Result<()> {
+		let output = Command::new("netsh")
+			.args(delete_args(bridge))
+			.output()?;
 
-			Ok((self.bridges.last().unwrap().clone(), true))
+		if !output.status.success() {
+			return Err(Error::backend("netsh", "DELETE", &output).into());
 		}
-	}
-}
 
+		self.bridges.retain(|v| v.listen_to != bridge.listen_to);
 
+		Ok(())
+	}
 
+	fn plan_add_bridge(&self, _current: &[Bridge], bridge: &Bridge) -> Vec<String> {
+		vec![format!("netsh {}", add_args(bridge).join(" "))]
+	}
 
-#[derive(Debug, Clone)]
-pub struct Bridge {
-	/// Randomly generated backend 127.*.*.* Ip Address.
-	pub listen_to: Connection,
-	/// Known IP Address used to connect to the Host Proxy.
-	pub connect_to: Connection
+	fn plan_delete_bridge(&self, _current: &[Bridge], bridge: &Bridge) -> Vec<String> {
+		vec![format!("netsh {}", delete_args(bridge).join(" "))]
+	}
 }
 
-impl Bridge {
-	fn delete(&self, proxy: ProxyBridge) -> Result<()> {
-		// netsh interface portproxy delete v4tov4 listenport=80 listenaddress=127.*.*.*
-		let output = Command::new("netsh")
-			.args(&[
-				"interface",
-				"portproxy",
-				"delete",
-				proxy.into_str(),
-				&format!("listenport={}", self.listen_to.port),
-				&format!("listenaddress={}", self.listen_to.address)
-			])
-			.output()?;
 
-		if !output.status.success() {
-			panic!("[netsh][DELETE]: {}", output.status);
-		}
+/// netsh interface portproxy add v4tov4 listenaddress=127.*.*.* listenport=80 connectaddress=127.0.0.1 connectport=****
+fn add_args(bridge: &Bridge) -> Vec<String> {
+	vec![
+		"interface".to_string(),
+		"portproxy".to_string(),
+		"add".to_string(),
+		ProxyBridge::between(bridge).into_str().to_string(),
+		format!("listenaddress={}", bridge.listen_to.address),
+		format!("listenport={}", bridge.listen_to.port),
+		format!("connectaddress={}", bridge.connect_to.address),
+		format!("connectport={}", bridge.connect_to.port)
+	]
+}
 
-		Ok(())
-	}
+/// netsh interface portproxy delete v4tov4 listenport=80 listenaddress=127.*.*.*
+fn delete_args(bridge: &Bridge) -> Vec<String> {
+	vec![
+		"interface".to_string(),
+		"portproxy".to_string(),
+		"delete".to_string(),
+		ProxyBridge::between(bridge).into_str().to_string(),
+		format!("listenport={}", bridge.listen_to.port),
+		format!("listenaddress={}", bridge.listen_to.address)
+	]
 }
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProxyBridge {
 	V4ToV4,
-	// V4ToV6,
-	// V6ToV4,
-	// V6ToV6
+	V4ToV6,
+	V6ToV4,
+	V6ToV6
 }
 
 impl ProxyBridge {
+	pub const ALL: [ProxyBridge; 4] = [Self::V4ToV4, Self::V4ToV6, Self::V6ToV4, Self::V6ToV6];
+
+	/// Mode required to forward the bridges' listening address to its' connecting address.
+	pub fn between(bridge: &Bridge) -> Self {
+		match (bridge.listen_to.address.is_ipv4(), bridge.connect_to.address.is_ipv4()) {
+			(true, true) => Self::V4ToV4,
+			(true, false) => Self::V4ToV6,
+			(false, true) => Self::V6ToV4,
+			(false, false) => Self::V6ToV6
+		}
+	}
+
 	pub fn into_str(self) -> &'static str {
 		match self {
 			Self::V4ToV4 => "v4tov4",
-			// Self::V4ToV6 => "v4tov6",
-			// Self::V6ToV4 => "v6tov4",
-			// Self::V6ToV6 => "v6tov6",
+			Self::V4ToV6 => "v4tov6",
+			Self::V6ToV4 => "v6tov4",
+			Self::V6ToV6 => "v6tov6",
 		}
 	}
 }
-
-
-
-fn gen_octets() -> (u8, u8, u8) {
-	let range = rand::random::<u32>();
-
-	let addr_0 = range & 0xFF;
-	let addr_1 = (range >> 8) & 0xFF;
-	let addr_2 = (range >> 16) & 0xFF;
-
-	(addr_0 as u8, addr_1 as u8, addr_2 as u8)
-}
\ No newline at end of file
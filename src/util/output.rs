@@ -0,0 +1,112 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Bridge, Error, Step};
+
+
+/// How command results are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+	/// Human readable text.
+	#[default]
+	Table,
+	Json,
+	Yaml
+}
+
+impl Format {
+	pub fn render<R: Report>(self, report: &R) -> Result<String> {
+		Ok(match self {
+			Self::Table => report.table(),
+			Self::Json => serde_json::to_string_pretty(report)?,
+			Self::Yaml => serde_yaml::to_string(report)?.trim_end().to_string()
+		})
+	}
+}
+
+impl FromStr for Format {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"table" => Ok(Self::Table),
+			"json" => Ok(Self::Json),
+			"yaml" => Ok(Self::Yaml),
+			_ => Err(Error::parse(s, "Expected table, json or yaml"))
+		}
+	}
+}
+
+
+/// Result of a command. Serialized for json and yaml.
+pub trait Report: Serialize {
+	/// Human readable version.
+	fn table(&self) -> String;
+}
+
+
+/// Something a command changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Change {
+	AddHost {
+		address: IpAddr,
+		host: String
+	},
+	DeleteHost {
+		address: IpAddr,
+		host: String
+	},
+	AddBridge(Bridge),
+	DeleteBridge(Bridge)
+}
+
+impl From<&Step> for Change {
+	fn from(step: &Step) -> Self {
+		match step.clone() {
+			Step::AddHost(address, host) => Self::AddHost { address, host },
+			Step::DeleteHost(address, host) => Self::DeleteHost { address, host },
+			Step::AddBridge(bridge) => Self::AddBridge(bridge),
+			Step::DeleteBridge(bridge) => Self::DeleteBridge(bridge)
+		}
+	}
+}
+
+impl fmt::Display for Change {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::AddHost { address, host } => write!(f, "Added Host {:?} -> {}", host, address),
+			Self::DeleteHost { address, host } => write!(f, "Removed Host {:?} -> {}", host, address),
+			Self::AddBridge(bridge) => write!(f, "Added Bridge {} -> {}", bridge.listen_to, bridge.connect_to),
+			Self::DeleteBridge(bridge) => write!(f, "Removed Bridge {} -> {}", bridge.listen_to, bridge.connect_to)
+		}
+	}
+}
+
+
+/// Lines up the columns of every row.
+pub fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+	let mut widths = header.map(|v| v.len());
+
+	for row in rows {
+		for (width, cell) in widths.iter_mut().zip(row) {
+			*width = (*width).max(cell.len());
+		}
+	}
+
+	std::iter::once(header.map(String::from))
+		.chain(rows.iter().cloned())
+		.map(|row| {
+			row.iter()
+				.zip(widths)
+				.map(|(cell, width)| format!("{:width$}", cell, width = width))
+				.collect::<Vec<_>>()
+				.join("  ")
+				.trim_end()
+				.to_string()
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{Bridge, Change, ForwardingBackend, Report};
+
+
+/// Records what the wrapped backend would run instead of running it.
+pub struct DryRun<'a> {
+	backend: &'a dyn ForwardingBackend,
+	bridges: Vec<Bridge>,
+	/// Commands in the order they would have been run.
+	pub commands: Vec<String>
+}
+
+impl<'a> DryRun<'a> {
+	pub fn new(backend: &'a dyn ForwardingBackend) -> Self {
+		Self {
+			backend,
+			bridges: backend.bridges().to_vec(),
+			commands: Vec::new()
+		}
+	}
+}
+
+impl ForwardingBackend for DryRun<'_> {
+	fn bridges(&self) -> &[Bridge] {
+		&self.bridges
+	}
+
+	fn add_bridge(&mut self, bridge: Bridge) -> Result<()> {
+		self.commands.extend(self.backend.plan_add_bridge(&self.bridges, &bridge));
+		self.bridges.push(bridge);
+
+		Ok(())
+	}
+
+	fn delete_bridge(&mut self, bridge: &Bridge) -> Result<()> {
+		self.commands.extend(self.backend.plan_delete_bridge(&self.bridges, bridge));
+		self.bridges.retain(|v| v.listen_to != bridge.listen_to);
+
+		Ok(())
+	}
+}
+
+
+/// What a command would change. Nothing has been changed.
+#[derive(Debug, Serialize)]
+pub struct Plan {
+	pub changes: Vec<Change>,
+	/// Changed lines of the hosts file prefixed with "-" or "+".
+	pub hosts_diff: Vec<String>,
+	/// Backend commands in the order they would be run.
+	pub commands: Vec<String>
+}
+
+impl Report for Plan {
+	fn table(&self) -> String {
+		let mut lines = vec!["Dry run. Nothing was changed.".to_string()];
+
+		if self.changes.is_empty() {
+			lines.push("Nothing to change.".to_string());
+			return lines.join("\n");
+		}
+
+		lines.extend(self.changes.iter().map(|v| format!("  {}", v)));
+
+		lines.push(String::new());
+		lines.push("Hosts file:".to_string());
+
+		if self.hosts_diff.is_empty() {
+			lines.push("  (unchanged)".to_string());
+		} else {
+			lines.extend(self.hosts_diff.iter().map(|v| format!("  {}", v)));
+		}
+
+		lines.push(String::new());
+		lines.push("Commands:".to_string());
+
+		if self.commands.is_empty() {
+			lines.push("  (none)".to_string());
+		} else {
+			for command in &self.commands {
+				lines.extend(command.lines().map(|v| format!("  {}", v)));
+			}
+		}
+
+		lines.join("\n")
+	}
+}
+
+
+/// Lines removed from `before` ("-") and added in `after` ("+"), in order.
+///
+/// Only the lines between the common start and end are compared, since hosts files can be huge
+/// but only ever change inside the managed block.
+pub fn diff_lines(before: &str, after: &str) -> Vec<String> {
+	let before = before.lines().collect::<Vec<_>>();
+	let after = after.lines().collect::<Vec<_>>();
+
+	let prefix = before.iter().zip(&after).take_while(|(a, b)| a == b).count();
+	let suffix = before[prefix..].iter().rev().zip(after[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+
+	let before = &before[prefix..before.len() - suffix];
+	let after = &after[prefix..after.len() - suffix];
+
+	// Length of the longest common subsequence of before[i..] and after[j..].
+	let mut lengths = vec![vec![0usize; after.len() + 1]; before.len() + 1];
+
+	for i in (0..before.len()).rev() {
+		for j in (0..after.len()).rev() {
+			lengths[i][j] = if before[i] == after[j] {
+				lengths[i + 1][j + 1] + 1
+			} else {
+				lengths[i + 1][j].max(lengths[i][j + 1])
+			};
+		}
+	}
+
+	let (mut i, mut j) = (0, 0);
+	let mut diff = Vec::new();
+
+	while i < before.len() || j < after.len() {
+		if i < before.len() && j < after.len() && before[i] == after[j] {
+			i += 1;
+			j += 1;
+		} else if j < after.len() && (i == before.len() || lengths[i][j + 1] > lengths[i + 1][j]) {
+			diff.push(format!("+{}", after[j]));
+			j += 1;
+		} else {
+			diff.push(format!("-{}", before[i]));
+			i += 1;
+		}
+	}
+
+	diff
+}
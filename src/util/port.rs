@@ -0,0 +1,208 @@
+use std::{fmt, net::TcpStream, time::Duration};
+
+use serde::Serialize;
+
+use crate::Connection;
+
+
+/// How long connecting may take before nothing is seen as listening.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+
+
+/// Whether something accepts connections on a port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum PortStatus {
+	Listening {
+		/// Process listening on it. None if the OS doesn't tell (missing rights for example).
+		#[serde(skip_serializing_if = "Option::is_none")]
+		owner: Option<PortOwner>
+	},
+	NotListening
+}
+
+impl PortStatus {
+	pub fn is_listening(&self) -> bool {
+		matches!(self, Self::Listening { .. })
+	}
+}
+
+impl fmt::Display for PortStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Listening { owner: Some(owner) } => write!(f, "listening ({})", owner),
+			Self::Listening { owner: None } => write!(f, "listening"),
+			Self::NotListening => write!(f, "not listening")
+		}
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortOwner {
+	pub pid: u32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>
+}
+
+impl fmt::Display for PortOwner {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.name {
+			Some(name) => write!(f, "{} {}", name, self.pid),
+			None => write!(f, "pid {}", self.pid)
+		}
+	}
+}
+
+
+impl Connection {
+	/// Whether something is listening on the connection and which process it is where the OS allows.
+	pub fn status(&self) -> PortStatus {
+		if TcpStream::connect_timeout(&(*self).into(), CONNECT_TIMEOUT).is_err() {
+			return PortStatus::NotListening;
+		}
+
+		PortStatus::Listening {
+			owner: find_owner(*self)
+		}
+	}
+}
+
+
+/// Matches the listening sockets in /proc/net up with the processes' file descriptors.
+#[cfg(target_os = "linux")]
+fn find_owner(connection: Connection) -> Option<PortOwner> {
+	use std::fs;
+
+	let inode = ["/proc/net/tcp", "/proc/net/tcp6"].iter()
+		.filter_map(|path| fs::read_to_string(path).ok())
+		.find_map(|table| table.lines().skip(1).find_map(|line| linux::listening_inode(line, connection)))?;
+
+	let socket = format!("socket:[{}]", inode);
+
+	for process in fs::read_dir("/proc").ok()?.flatten() {
+		let pid = match process.file_name().to_str().and_then(|v| v.parse::<u32>().ok()) {
+			Some(v) => v,
+			None => continue
+		};
+
+		// Only readable for our own processes unless running as root.
+		let fds = match fs::read_dir(process.path().join("fd")) {
+			Ok(v) => v,
+			Err(_) => continue
+		};
+
+		let owns = fds.flatten()
+			.filter_map(|fd| fs::read_link(fd.path()).ok())
+			.any(|target| target.to_str() == Some(socket.as_str()));
+
+		if owns {
+			return Some(PortOwner {
+				pid,
+				name: fs::read_to_string(process.path().join("comm")).ok().map(|v| v.trim().to_string())
+			});
+		}
+	}
+
+	None
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+	use crate::Connection;
+
+	const STATE_LISTEN: &str = "0A";
+
+	/// Inode of a "/proc/net/tcp" line listening on the connection, or on every address with its' port.
+	///
+	/// "0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000 1000 0 12345 ..."
+	pub fn listening_inode(line: &str, connection: Connection) -> Option<u64> {
+		let fields = line.split_ascii_whitespace().collect::<Vec<_>>();
+
+		if fields.get(3) != Some(&STATE_LISTEN) {
+			return None;
+		}
+
+		let (address, port) = fields.get(1)?.split_once(':')?;
+		let address = parse_address(address)?;
+
+		let matches = u16::from_str_radix(port, 16).ok()? == connection.port
+			&& (address == connection.address || address.is_unspecified());
+
+		if matches { fields.get(9)?.parse().ok() } else { None }
+	}
+
+	/// Addresses are written as 32 bit words in host byte order.
+	fn parse_address(value: &str) -> Option<IpAddr> {
+		let words = (0..value.len() / 8)
+			.map(|i| u32::from_str_radix(&value[i * 8..i * 8 + 8], 16))
+			.collect::<Result<Vec<_>, _>>()
+			.ok()?;
+
+		let bytes = words.iter().flat_map(|v| v.to_ne_bytes()).collect::<Vec<_>>();
+
+		match bytes.len() {
+			4 => Some(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]).into()),
+			16 => Some(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).into()),
+			_ => None
+		}
+	}
+}
+
+/// Parses "TCP 127.0.0.1:8080 0.0.0.0:0 LISTENING 1234" from netstat and looks the name up with tasklist.
+#[cfg(windows)]
+fn find_owner(connection: Connection) -> Option<PortOwner> {
+	use std::{net::SocketAddr, process::Command};
+
+	let output = Command::new("netstat").args(["-ano", "-p", "TCP"]).output().ok()?;
+	let output = String::from_utf8_lossy(&output.stdout).to_string();
+
+	let pid = output.lines().find_map(|line| {
+		let fields = line.split_ascii_whitespace().collect::<Vec<_>>();
+
+		if fields.len() != 5 || fields[3] != "LISTENING" {
+			return None;
+		}
+
+		let local = fields[1].parse::<SocketAddr>().ok()?;
+		let matches = local.port() == connection.port && (local.ip() == connection.address || local.ip().is_unspecified());
+
+		if matches { fields[4].parse::<u32>().ok() } else { None }
+	})?;
+
+	// "name.exe","1234",...
+	let name = Command::new("tasklist")
+		.args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+		.output()
+		.ok()
+		.and_then(|v| String::from_utf8_lossy(&v.stdout).split(',').next().map(|v| v.trim_matches('"').to_string()))
+		.filter(|v| !v.is_empty() && !v.starts_with("INFO"));
+
+	Some(PortOwner {
+		pid,
+		name
+	})
+}
+
+/// Asks lsof, which prints "p1234" and "cname" lines.
+#[cfg(not(any(target_os = "linux", windows)))]
+fn find_owner(connection: Connection) -> Option<PortOwner> {
+	use std::process::Command;
+
+	let output = Command::new("lsof")
+		.args(["-nP", &format!("-iTCP@{}:{}", connection.address, connection.port), "-sTCP:LISTEN", "-Fpc"])
+		.output()
+		.ok()?;
+
+	let output = String::from_utf8_lossy(&output.stdout).to_string();
+
+	let pid = output.lines().find_map(|v| v.strip_prefix('p')?.parse().ok())?;
+	let name = output.lines().find_map(|v| v.strip_prefix('c')).map(String::from);
+
+	Some(PortOwner {
+		pid,
+		name
+	})
+}
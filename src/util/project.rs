@@ -0,0 +1,81 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::allocator::fnv1a;
+use crate::{Connection, Error, Mapping, DEFAULT_LISTEN_PORT};
+
+
+/// Name of the project file looked for in the current directory and its' parents.
+pub const PROJECT_FILE_NAME: &str = "localhosting.toml";
+
+
+/// Hosts a project needs. Applied with "up" and removed again with "down".
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Project {
+	/// Stored with every host the project adds. Defaults to the name of the directory the file is in
+	/// followed by a hash of the files' path, so projects in directories with the same name don't collide.
+	#[serde(default)]
+	pub id: String,
+	#[serde(default, rename = "hosts")]
+	pub entries: Vec<ProjectEntry>
+}
+
+impl Project {
+	/// Finds the project file in `dir` or the closest parent which has one.
+	pub fn find(dir: &Path) -> Result<PathBuf> {
+		dir.ancestors()
+			.map(|v| v.join(PROJECT_FILE_NAME))
+			.find(|v| v.is_file())
+			.ok_or_else(|| Error::NotFound(dir.join(PROJECT_FILE_NAME).display().to_string()).into())
+	}
+
+	pub fn load(path: &Path) -> Result<Self> {
+		let contents = fs::read_to_string(path)
+			.map_err(|_| Error::NotFound(path.display().to_string()))?;
+
+		let mut project = Self::parse(&contents)?;
+
+		if project.id.is_empty() {
+			project.id = default_id(&path.canonicalize()?);
+		}
+
+		Ok(project)
+	}
+
+	pub fn parse(value: &str) -> Result<Self> {
+		Ok(toml::from_str(value).map_err(|e| Error::parse(PROJECT_FILE_NAME, e))?)
+	}
+}
+
+
+/// "app-1b2c3d4e" for "/home/user/app/localhosting.toml".
+fn default_id(path: &Path) -> String {
+	let name = path.parent()
+		.and_then(Path::file_name)
+		.map(|v| v.to_string_lossy().to_string())
+		.unwrap_or_default();
+
+	format!("{}-{:08x}", name, fnv1a(path.to_string_lossy().as_bytes()) as u32)
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ProjectEntry {
+	/// Host name (example.com)
+	pub host: String,
+	/// Address the host is forwarded to (127.0.0.1:8080)
+	pub target: Connection,
+	/// Port the host listens on. Defaults to 80.
+	#[serde(default)]
+	pub listen_port: Option<u16>
+}
+
+impl ProjectEntry {
+	/// Whether the mapping forwards this entry.
+	pub fn is_mapped_by(&self, mapping: &Mapping) -> bool {
+		mapping.connect_to == self.target
+			&& mapping.listen_to.port == self.listen_port.unwrap_or(DEFAULT_LISTEN_PORT)
+	}
+}
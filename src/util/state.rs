@@ -0,0 +1,274 @@
+use std::{
+	collections::BTreeMap,
+	fs,
+	net::IpAddr,
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH}
+};
+
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{Allocator, Connection, Error, ForwardingBackend, HostFile, Step, write_atomic};
+
+
+/// Every mapping localhosting manages. Used instead of matching bridges and hosts up by address.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+	/// Where the state is persisted. Kept in memory only if None.
+	#[serde(skip)]
+	path: Option<PathBuf>,
+	/// Used for the listening address of new bridges. Stored so the settings only have to be given once.
+	#[serde(default)]
+	pub allocator: Allocator,
+	pub mappings: Vec<Mapping>,
+	/// Last listening address of every host. Reused when the host is added again.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub allocations: BTreeMap<String, IpAddr>
+}
+
+impl State {
+	/// Default location of the state inside the users' config directory.
+	pub fn default_path() -> Result<PathBuf> {
+		let dir = dirs::config_dir().context("Unable to find config directory")?;
+
+		Ok(dir.join("localhosting").join("state.json"))
+	}
+
+	/// Loads the state, returning None if it was never saved.
+	pub fn load(path: PathBuf) -> Result<Option<Self>> {
+		if !path.exists() {
+			return Ok(None);
+		}
+
+		let mut state: Self = serde_json::from_str(&fs::read_to_string(&path)?)
+			.with_context(|| format!("Unable to read state {}", path.display()))?;
+
+		state.path = Some(path);
+
+		Ok(Some(state))
+	}
+
+	/// Loads the state. If it doesn't exist yet it's discovered from the bridges and hosts instead.
+	///
+	/// A discovered state is only written once something is changed.
+	pub fn load_or_discover(path: PathBuf, backend: &dyn ForwardingBackend, hosts: &HostFile) -> Result<Self> {
+		if let Some(state) = Self::load(path.clone())? {
+			return Ok(state);
+		}
+
+		let mut state = Self {
+			path: Some(path),
+			..Self::default()
+		};
+
+		state.reconcile(backend, hosts);
+
+		Ok(state)
+	}
+
+	/// Where the state is persisted. None if it's kept in memory only.
+	pub fn path(&self) -> Option<&Path> {
+		self.path.as_deref()
+	}
+
+	/// Copy which is kept in memory only. Saving it does nothing.
+	pub fn detached(&self) -> Self {
+		Self {
+			path: None,
+			allocator: self.allocator,
+			mappings: self.mappings.clone(),
+			allocations: self.allocations.clone()
+		}
+	}
+
+	pub fn save(&self) -> Result<()> {
+		let path = match &self.path {
+			Some(v) => v,
+			None => return Ok(())
+		};
+
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+
+		write_atomic(path, &serde_json::to_string_pretty(self)?)
+	}
+
+	/// Finds the mapping using either its' target or listening connection.
+	pub fn find_by_connection(&self, value: Connection) -> Option<&Mapping> {
+		self.mappings.iter().find(|v| v.connect_to == value || v.listen_to == value)
+	}
+
+	pub fn find_by_host(&self, value: &str) -> Option<&Mapping> {
+		self.mappings.iter().find(|v| v.hosts.iter().any(|h| h.eq_ignore_ascii_case(value)))
+	}
+
+	/// Every mapping of the host. There's one per listening port.
+	pub fn filter_by_host<'a>(&'a self, value: &'a str) -> impl Iterator<Item = &'a Mapping> + 'a {
+		self.mappings.iter().filter(move |v| v.hosts.iter().any(|h| h.eq_ignore_ascii_case(value)))
+	}
+
+	/// Listening connection and host of every host on every bridge.
+	pub fn pairs(&self) -> Vec<(Connection, String)> {
+		self.mappings.iter()
+			.flat_map(|mapping| mapping.hosts.iter().map(move |host| (mapping.listen_to, host.clone())))
+			.collect()
+	}
+
+	/// Mappings matching the target. Empty if nothing matched.
+	pub fn find_target(&self, target: &Target) -> Vec<&Mapping> {
+		match target {
+			Target::Connection(connection) => self.find_by_connection(*connection).into_iter().collect(),
+			Target::Host(host, port) => self.mappings.iter()
+				.filter(|v| v.hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+				.filter(|v| port.map(|p| v.listen_to.port == p).unwrap_or(true))
+				.collect()
+		}
+	}
+
+	/// Finds an unused listening address for the host, preferring the one it had before.
+	///
+	/// A host which already has a mapping keeps its' address since the hosts file can only point it at one.
+	/// Addresses of stored mappings are seen as used even if their bridge went missing.
+	pub fn allocate(&self, host: &str, port: u16, backend: &dyn ForwardingBackend) -> Result<Connection> {
+		if let Some(mapping) = self.find_by_host(host) {
+			return Ok((mapping.listen_to.address, port).into());
+		}
+
+		self.allocator.allocate(host, port, self.allocations.get(host).copied(), |address| {
+			backend.bridges().iter().any(|v| v.listen_to.address == address)
+				|| self.mappings.iter().any(|v| v.listen_to.address == address)
+		})
+	}
+
+	/// Records a completed step.
+	pub fn apply(&mut self, step: &Step) {
+		match step {
+			Step::AddBridge(bridge) => {
+				if self.find_by_connection(bridge.listen_to).is_none() {
+					// Hosts pointing at the address are served by every port on it.
+					let hosts = self.mappings.iter()
+						.find(|v| v.listen_to.address == bridge.listen_to.address)
+						.map(|v| v.hosts.clone())
+						.unwrap_or_default();
+
+					self.mappings.push(Mapping {
+						connect_to: bridge.connect_to,
+						listen_to: bridge.listen_to,
+						hosts,
+						created: now(),
+						owners: BTreeMap::new()
+					});
+				}
+			}
+
+			Step::DeleteBridge(bridge) => {
+				self.mappings.retain(|v| v.listen_to != bridge.listen_to);
+			}
+
+			Step::AddHost(address, host) => {
+				self.allocations.insert(host.clone(), *address);
+
+				for mapping in self.mappings.iter_mut().filter(|v| &v.listen_to.address == address) {
+					if !mapping.hosts.contains(host) {
+						mapping.hosts.push(host.clone());
+					}
+				}
+			}
+
+			Step::DeleteHost(address, host) => {
+				for mapping in self.mappings.iter_mut().filter(|v| &v.listen_to.address == address) {
+					mapping.hosts.retain(|v| v != host);
+					mapping.owners.remove(host);
+				}
+			}
+		}
+	}
+
+	/// Adds mappings for bridges which have managed hosts pointing at them but aren't stored yet.
+	pub fn reconcile(&mut self, backend: &dyn ForwardingBackend, hosts: &HostFile) {
+		for bridge in backend.bridges() {
+			if self.find_by_connection(bridge.listen_to).is_some() {
+				continue;
+			}
+
+			let names = hosts.find_by_address(bridge.listen_to.address)
+				.into_iter()
+				.filter(|v| v.managed)
+				.flat_map(|v| v.names())
+				.map(|v| v.to_string())
+				.collect::<Vec<_>>();
+
+			if !names.is_empty() {
+				self.mappings.push(Mapping {
+					connect_to: bridge.connect_to,
+					listen_to: bridge.listen_to,
+					hosts: names,
+					created: now(),
+					owners: BTreeMap::new()
+				});
+			}
+		}
+	}
+}
+
+
+/// What a command was given to find mappings with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+	/// Target or listening connection (127.0.0.1:8080)
+	Connection(Connection),
+	/// Host with an optional listening port (example.com or example.com:443)
+	Host(&'a str, Option<u16>)
+}
+
+impl<'a> Target<'a> {
+	pub fn parse(value: &'a str) -> Result<Self> {
+		if !value.contains(':') {
+			return Ok(Self::Host(value, None));
+		}
+
+		let error = match value.parse::<Connection>() {
+			Ok(v) => return Ok(Self::Connection(v)),
+			Err(e) => e
+		};
+
+		match value.rsplit_once(':') {
+			Some((host, port)) if !host.contains(':') && host.parse::<IpAddr>().is_err() => {
+				Ok(Self::Host(host, Some(port.parse().map_err(|e| Error::parse(value, e))?)))
+			}
+
+			_ => Err(error)
+		}
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mapping {
+	/// Known IP Address the hosts are forwarded to.
+	pub connect_to: Connection,
+	/// Allocated 127.*.*.* address the hosts point at and the port it listens on.
+	pub listen_to: Connection,
+	pub hosts: Vec<String>,
+	/// Unix timestamp (seconds) of when it was added.
+	pub created: u64,
+	/// Project which added each host. Hosts without one were added some other way.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub owners: BTreeMap<String, String>
+}
+
+impl Mapping {
+	pub fn is_owned_by(&self, host: &str, project_id: &str) -> bool {
+		self.owners.get(host).map(|v| v == project_id).unwrap_or_default()
+	}
+}
+
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|v| v.as_secs())
+		.unwrap_or_default()
+}
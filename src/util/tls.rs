@@ -0,0 +1,99 @@
+use std::{fmt, fs, io::BufReader, sync::Arc};
+
+use anyhow::Result;
+use rustls::{
+	ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig,
+	internal::pemfile::{certs, pkcs8_private_keys},
+	sign::{CertifiedKey, any_supported_type}
+};
+
+use crate::{CertStore, Error, HostFile, Leaf, State, is_wildcard};
+
+
+/// Picks the certificate of the host the client asked for (SNI).
+///
+/// Exact names win over wildcards. Like browsers, "*.example.com" only matches a single label.
+#[derive(Clone, Default)]
+pub struct CertResolver {
+	certs: Vec<(String, CertifiedKey)>
+}
+
+impl CertResolver {
+	/// Uses the issued certificate of every managed hosts file line and stored wildcard which has one.
+	pub fn load(store: &CertStore, hosts: &HostFile, state: &State) -> Result<Self> {
+		let mut resolver = Self::default();
+
+		let lines = hosts.items.iter().filter(|v| v.managed).map(|v| &v.host);
+		let wildcards = state.mappings.iter().flat_map(|v| &v.hosts).filter(|v| is_wildcard(v));
+
+		for host in lines.chain(wildcards) {
+			if let Some(leaf) = store.leaf(host)? {
+				resolver.add(&leaf)?;
+			}
+		}
+
+		Ok(resolver)
+	}
+
+	/// Serves the certificate for every name it was issued for.
+	pub fn add(&mut self, leaf: &Leaf) -> Result<()> {
+		let chain = certs(&mut BufReader::new(fs::File::open(&leaf.cert_path)?))
+			.map_err(|_| Error::parse(&leaf.cert_path.display().to_string(), "Invalid certificate"))?;
+
+		let key = pkcs8_private_keys(&mut BufReader::new(fs::File::open(&leaf.key_path)?))
+			.ok()
+			.and_then(|v| v.into_iter().next())
+			.ok_or_else(|| Error::parse(&leaf.key_path.display().to_string(), "Expected a PKCS8 private key"))?;
+
+		let key = any_supported_type(&key)
+			.map_err(|_| Error::parse(&leaf.key_path.display().to_string(), "Unsupported private key"))?;
+
+		let certified = CertifiedKey::new(chain, Arc::new(key));
+
+		// Looked up by the lowercased SNI name.
+		for name in leaf.names.iter().map(|v| v.to_ascii_lowercase()) {
+			self.certs.retain(|(v, _)| *v != name);
+			self.certs.push((name, certified.clone()));
+		}
+
+		Ok(())
+	}
+
+	/// Names there is a certificate for.
+	pub fn names(&self) -> impl Iterator<Item = &str> {
+		self.certs.iter().map(|(name, _)| name.as_str())
+	}
+
+	pub fn find(&self, name: &str) -> Option<&CertifiedKey> {
+		let name = name.trim_end_matches('.').to_ascii_lowercase();
+		let wildcard = name.split_once('.').map(|(_, rest)| format!("*.{}", rest));
+
+		self.certs.iter()
+			.find(|(v, _)| *v == name)
+			.or_else(|| self.certs.iter().find(|(v, _)| Some(v) == wildcard.as_ref()))
+			.map(|(_, key)| key)
+	}
+
+	/// Server config choosing certificates with this resolver.
+	pub fn server_config(self) -> ServerConfig {
+		let mut config = ServerConfig::new(NoClientAuth::new());
+		config.cert_resolver = Arc::new(self);
+		config.set_protocols(&[b"http/1.1".to_vec()]);
+
+		config
+	}
+}
+
+impl ResolvesServerCert for CertResolver {
+	fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+		let name: &str = client_hello.server_name()?.into();
+
+		self.find(name).cloned()
+	}
+}
+
+impl fmt::Debug for CertResolver {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_list().entries(self.names()).finish()
+	}
+}
@@ -0,0 +1,97 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Result, Context};
+
+use crate::{Bridge, ForwardingBackend, write_atomic};
+
+
+/// Bridges which are only recorded to a file. The forwarding itself is done by the "serve" command.
+pub struct UserspaceProxy {
+	path: PathBuf,
+	bridges: Vec<Bridge>
+}
+
+impl UserspaceProxy {
+	/// Default location of the bridges file inside the users' config directory.
+	pub fn default_path() -> Result<PathBuf> {
+		let dir = dirs::config_dir().context("Unable to find config directory")?;
+
+		Ok(dir.join("localhosting").join("bridges"))
+	}
+
+	pub fn load(path: PathBuf) -> Result<Self> {
+		let bridges = if path.exists() {
+			fs::read_to_string(&path)?
+				.lines()
+				// Each line is "<listen_to> <connect_to>"
+				.filter_map(|line| {
+					let mut split = line.split_ascii_whitespace();
+
+					Some(Bridge {
+						listen_to: split.next()?.parse().ok()?,
+						connect_to: split.next()?.parse().ok()?
+					})
+				})
+				.collect()
+		} else {
+			Vec::new()
+		};
+
+		Ok(Self {
+			path,
+			bridges
+		})
+	}
+
+	fn save(&self) -> Result<()> {
+		if let Some(parent) = self.path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+
+		write_atomic(&self.path, contents(&self.bridges))
+	}
+
+	fn plan(&self, bridges: &[Bridge]) -> Vec<String> {
+		vec![format!("write {}:\n{}", self.path.display(), contents(bridges))]
+	}
+}
+
+impl ForwardingBackend for UserspaceProxy {
+	fn bridges(&self) -> &[Bridge] {
+		&self.bridges
+	}
+
+	fn add_bridge(&mut self, bridge: Bridge) -> Result<()> {
+		self.bridges.push(bridge);
+		self.save()
+	}
+
+	fn delete_bridge(&mut self, bridge: &Bridge) -> Result<()> {
+		self.bridges.retain(|v| v.listen_to != bridge.listen_to);
+		self.save()
+	}
+
+	fn plan_add_bridge(&self, current: &[Bridge], bridge: &Bridge) -> Vec<String> {
+		let mut bridges = current.to_vec();
+		bridges.push(bridge.clone());
+
+		self.plan(&bridges)
+	}
+
+	fn plan_delete_bridge(&self, current: &[Bridge], bridge: &Bridge) -> Vec<String> {
+		let bridges = current.iter()
+			.filter(|v| v.listen_to != bridge.listen_to)
+			.cloned()
+			.collect::<Vec<_>>();
+
+		self.plan(&bridges)
+	}
+}
+
+
+/// Each line is "<listen_to> <connect_to>"
+fn contents(bridges: &[Bridge]) -> String {
+	bridges.iter()
+		.map(|v| format!("{} {}\n", v.listen_to, v.connect_to))
+		.collect()
+}